@@ -0,0 +1,91 @@
+//! Compares sequential and chunked uploads and downloads against a real server.
+//!
+//! Needs `BENCH_HOST`, `BENCH_USER` and `BENCH_KEYFILE`, optionally `BENCH_PORT`, `BENCH_SIZE`
+//! (default 256MiB) and `BENCH_REMOTE_DIR` (default /tmp). Skipped when the host is not set.
+//!
+//!     BENCH_HOST=example BENCH_USER=me BENCH_KEYFILE=~/.ssh/id_ed25519 cargo bench --bench chunked
+
+use std::{env, path::{Path, PathBuf}, time::Instant};
+use learning_openssh::{ChunkOptions, Client, ConnectOptions, GetOptions, PutOptions, Result, parse_size};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let Ok(host) = env::var("BENCH_HOST") else {
+        eprintln!("BENCH_HOST not set, skipping");
+        return Ok(());
+    };
+    let options = ConnectOptions {
+        user: env::var("BENCH_USER").unwrap_or_else(|_| "root".to_string()),
+        host,
+        port: env::var("BENCH_PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(22),
+        keyfile: env::var("BENCH_KEYFILE").map(PathBuf::from).unwrap_or_default(),
+    };
+    let size = parse_size(&env::var("BENCH_SIZE").unwrap_or_else(|_| "256MiB".to_string()))?;
+    let remote_dir = PathBuf::from(env::var("BENCH_REMOTE_DIR").unwrap_or_else(|_| "/tmp".to_string()));
+
+    let local_path = env::temp_dir().join(format!("chunked-bench-{}", std::process::id()));
+    let fetched_path = local_path.with_extension("fetched");
+    let remote_path = remote_dir.join(local_path.file_name().unwrap());
+    write_test_file(&local_path, size).await?;
+
+    let client = Client::connect(options).await?;
+
+    let start = Instant::now();
+    client.put_file(&local_path, &remote_path, &PutOptions::default()).await?;
+    report("put sequential", size, start);
+
+    let start = Instant::now();
+    client.get_file(&remote_path, &fetched_path, &GetOptions::default()).await?;
+    report("get sequential", size, start);
+
+    for parallelism in [2, 4, 8, 16] {
+        for chunk_size in [1 << 20, 8 << 20, 32 << 20] {
+            let chunks = ChunkOptions { chunk_size, parallelism };
+            let label = format!("x{} {}MiB", parallelism, chunk_size >> 20);
+
+            let start = Instant::now();
+            client.put_file_chunked(&local_path, &remote_path, &PutOptions::default(), &chunks).await?;
+            report(&format!("put chunked {}", label), size, start);
+
+            let start = Instant::now();
+            client.get_file_chunked(&remote_path, &fetched_path, &GetOptions::default(), &chunks).await?;
+            report(&format!("get chunked {}", label), size, start);
+        }
+    }
+
+    client.remove_file(&remote_path).await?;
+    client.close().await?;
+    let _ = tokio::fs::remove_file(&local_path).await;
+    let _ = tokio::fs::remove_file(&fetched_path).await;
+
+    Ok(())
+}
+
+async fn write_test_file(path: &Path, size: u64) -> Result<()> {
+
+    // cheap incompressible-ish data, so ssh compression can't flatter either path
+
+    let mut file = File::create(path).await?;
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut block = vec![0u8; 1 << 20];
+    let mut left = size;
+    while left > 0 {
+        for byte in block.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = state as u8;
+        }
+        let n = left.min(block.len() as u64) as usize;
+        file.write_all(&block[..n]).await?;
+        left -= n as u64;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+fn report(label: &str, size: u64, start: Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("{:<24} {:>8.2}s {:>9.1} MiB/s", label, elapsed, size as f64 / (1 << 20) as f64 / elapsed);
+}
@@ -0,0 +1,168 @@
+use std::{io, path::Path, process::{ExitStatus, Stdio as LocalStdio}};
+use openssh::Stdio;
+use shell_escape::unix::escape;
+use tokio::{io::{copy, AsyncRead, AsyncWrite, AsyncWriteExt}, process::{Child, ChildStdin, ChildStdout, Command as LocalCommand}};
+use crate::{client::{Client, check_status}, error::{Error, Result}};
+
+/// Compression of the tar stream, applied by the `gzip` or `zstd` binaries on both ends.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn compressor(&self) -> Option<&'static [&'static str]> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some(&["gzip", "-c"]),
+            Self::Zstd => Some(&["zstd", "-q", "-c"]),
+        }
+    }
+
+    fn decompressor(&self) -> Option<&'static [&'static str]> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some(&["gzip", "-dc"]),
+            Self::Zstd => Some(&["zstd", "-q", "-dc"]),
+        }
+    }
+}
+
+impl Client {
+    /// Uploads a directory tree as one tar stream into `tar -x` on the remote host.
+    ///
+    /// Far fewer round trips than [`Client::put_dir`] for many small files, but it needs tar on both
+    /// ends and fails as a whole. Modes and symlinks are kept by tar.
+    pub async fn put_dir_tar(&self, local_dir: &Path, remote_dir: &Path, compression: Compression) -> Result<()> {
+        let remote_dir = escape(remote_dir.to_string_lossy());
+        let command = match compression.decompressor() {
+            Some(decompressor) => format!("mkdir -p -- {0} && cd -- {0} && {1} | tar -xf -", remote_dir, decompressor.join(" ")),
+            None => format!("mkdir -p -- {0} && cd -- {0} && tar -xf -", remote_dir),
+        };
+        let mut remote = self.session().raw_command(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .await?;
+
+        let mut tar = LocalCommand::new("tar");
+        tar.arg("-cf").arg("-").arg("-C").arg(local_dir).arg(".");
+        let mut local = LocalPipeline::spawn_source(tar, compression.compressor())?;
+
+        let mut stdin = remote.stdin().take().expect("stdin is piped");
+        let copied = pump(local.stdout.take().expect("stdout is piped"), &mut stdin).await;
+        drop(stdin);
+
+        // the remote status explains most failures, so it goes first
+
+        check_status(&command, remote.wait().await)?;
+        local.wait().await?;
+        copied
+    }
+
+    /// Downloads a directory tree as one tar stream out of `tar -c` on the remote host.
+    pub async fn get_dir_tar(&self, remote_dir: &Path, local_dir: &Path, compression: Compression) -> Result<()> {
+        let remote_dir = escape(remote_dir.to_string_lossy());
+
+        // without pipefail in every sh, tar's status is passed around the compressor by hand
+
+        let command = match compression.compressor() {
+            Some(compressor) => format!(
+                "cd -- {} && {{ s=$( {{ {{ tar -cf - .; echo $? >&4; }} | {} >&3; }} 4>&1 ); exit ${{s:-1}}; }} 3>&1",
+                remote_dir, compressor.join(" "),
+            ),
+            None => format!("cd -- {} && tar -cf - .", remote_dir),
+        };
+        let mut remote = self.session().raw_command(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .await?;
+
+        tokio::fs::create_dir_all(local_dir).await?;
+        let mut tar = LocalCommand::new("tar");
+        tar.arg("-xf").arg("-").arg("-C").arg(local_dir);
+        let mut local = LocalPipeline::spawn_sink(tar, compression.decompressor())?;
+
+        let stdout = remote.stdout().take().expect("stdout is piped");
+        let copied = pump(stdout, local.stdin.take().expect("stdin is piped")).await;
+
+        check_status(&command, remote.wait().await)?;
+        local.wait().await?;
+        copied
+    }
+}
+
+// tar plus an optional compression stage, wired up without a local shell
+
+struct LocalPipeline {
+    children: Vec<(&'static str, Child)>,
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+}
+
+impl LocalPipeline {
+    /// `tar -c | compressor`, reading from the last stdout.
+    fn spawn_source(mut tar: LocalCommand, compressor: Option<&[&'static str]>) -> Result<Self> {
+        let mut tar = tar.stdin(LocalStdio::null()).stdout(LocalStdio::piped()).spawn()?;
+        let mut stdout = tar.stdout.take();
+        let mut children = vec![("tar", tar)];
+
+        if let Some(compressor) = compressor {
+            let input: LocalStdio = stdout.take().expect("stdout is piped").try_into()?;
+            let mut child = LocalCommand::new(compressor[0]).args(&compressor[1..]).stdin(input).stdout(LocalStdio::piped()).spawn()?;
+            stdout = child.stdout.take();
+            children.push((compressor[0], child));
+        }
+        Ok(Self { children, stdin: None, stdout })
+    }
+
+    /// `decompressor | tar -x`, writing to the first stdin.
+    fn spawn_sink(mut tar: LocalCommand, decompressor: Option<&[&'static str]>) -> Result<Self> {
+        let mut children = Vec::new();
+        let mut stdin = None;
+
+        if let Some(decompressor) = decompressor {
+            let mut child = LocalCommand::new(decompressor[0]).args(&decompressor[1..]).stdin(LocalStdio::piped()).stdout(LocalStdio::piped()).spawn()?;
+            stdin = child.stdin.take();
+            let output: LocalStdio = child.stdout.take().expect("stdout is piped").try_into()?;
+            tar.stdin(output);
+            children.push((decompressor[0], child));
+        } else {
+            tar.stdin(LocalStdio::piped());
+        }
+
+        let mut tar = tar.stdout(LocalStdio::null()).spawn()?;
+        if stdin.is_none() {
+            stdin = tar.stdin.take();
+        }
+        children.push(("tar", tar));
+        Ok(Self { children, stdin, stdout: None })
+    }
+
+    async fn wait(mut self) -> Result<()> {
+        drop(self.stdin.take());
+        for (name, child) in &mut self.children {
+            check_local(name, child.wait().await?)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_local(name: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(Error::Io(io::Error::other(format!("local {} failed: {}", name, status))))
+}
+
+async fn pump(mut from: impl AsyncRead + Unpin, mut to: impl AsyncWrite + Unpin) -> Result<()> {
+    copy(&mut from, &mut to).await?;
+    to.shutdown().await?;
+    Ok(())
+}
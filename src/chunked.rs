@@ -0,0 +1,120 @@
+use std::{io::SeekFrom, ops::Range, os::unix::fs::PermissionsExt, path::Path};
+use futures::{StreamExt, TryStreamExt, stream};
+use openssh_sftp_client::file::TokioCompatFile;
+use tokio::{fs::File, io::{copy, AsyncReadExt, AsyncSeekExt, AsyncWriteExt}};
+use crate::{client::{Client, GetOptions, PutOptions}, error::{Error, Result}, hash::{HashAlgorithm, Hasher}, limit::LimitedReader, sftp::{apply_metadata, permissions_from_mode}};
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 8 << 20;
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+/// How a single large file is split for [`Client::put_file_chunked`] and [`Client::get_file_chunked`].
+#[derive(Debug, Clone)]
+pub struct ChunkOptions {
+    /// Bytes per range, each range goes through its own sftp handle
+    pub chunk_size: u64,
+
+    /// Ranges in flight at once
+    pub parallelism: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self { chunk_size: DEFAULT_CHUNK_SIZE, parallelism: DEFAULT_PARALLELISM }
+    }
+}
+
+impl Client {
+    /// Uploads a file as ranges written concurrently at their offsets.
+    ///
+    /// Only `verify` and `limit` of `options` apply, the destination is written in place.
+    pub async fn put_file_chunked(&self, local_path: &Path, remote_path: &Path, options: &PutOptions, chunks: &ChunkOptions) -> Result<()> {
+        let local_metadata = tokio::fs::metadata(local_path).await?;
+        let len = local_metadata.len();
+        let sftp = self.sftp().await?;
+
+        // sized up front, so ranges landing out of order never leave holes at the end
+
+        let mut remote_file = sftp.create(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        remote_file.set_len(len).await?;
+        drop(remote_file);
+
+        stream::iter(ranges(len, chunks.chunk_size))
+            .map(|range| async move {
+                let mut local_file = File::open(local_path).await?;
+                local_file.seek(SeekFrom::Start(range.start)).await?;
+                let mut local_file = LimitedReader::new(local_file.take(range.end - range.start), options.limit.clone());
+
+                let remote_file = sftp.options().write(true).open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+                let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+                remote_file.seek(SeekFrom::Start(range.start)).await?;
+                copy(&mut local_file, &mut remote_file).await?;
+
+                Ok::<_, Error>(())
+            })
+            .buffer_unordered(chunks.parallelism.max(1))
+            .try_collect::<()>()
+            .await?;
+
+        sftp.fs().set_permissions(remote_path, permissions_from_mode(local_metadata.permissions().mode())).await?;
+
+        if let Some(algorithm) = options.verify {
+            self.verify_remote_hash(remote_path, algorithm, local_file_hash(local_path, algorithm).await?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads a file as ranges read concurrently from their offsets.
+    ///
+    /// Only `preserve`, `verify` and `limit` of `options` apply, the destination is written in place.
+    pub async fn get_file_chunked(&self, remote_path: &Path, local_path: &Path, options: &GetOptions, chunks: &ChunkOptions) -> Result<()> {
+        let sftp = self.sftp().await?;
+        let metadata = sftp.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?.metadata().await?;
+        let len = metadata.len().unwrap_or(0);
+
+        let local_file = File::create(local_path).await?;
+        local_file.set_len(len).await?;
+        drop(local_file);
+
+        stream::iter(ranges(len, chunks.chunk_size))
+            .map(|range| async move {
+                let remote_file = sftp.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+                let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+                remote_file.seek(SeekFrom::Start(range.start)).await?;
+                let mut remote_file = LimitedReader::new(remote_file.take(range.end - range.start), options.limit.clone());
+
+                let mut local_file = File::options().write(true).open(local_path).await?;
+                local_file.seek(SeekFrom::Start(range.start)).await?;
+                copy(&mut remote_file, &mut local_file).await?;
+                local_file.flush().await?;
+
+                Ok::<_, Error>(())
+            })
+            .buffer_unordered(chunks.parallelism.max(1))
+            .try_collect::<()>()
+            .await?;
+
+        if let Some(algorithm) = options.verify {
+            self.verify_remote_hash(remote_path, algorithm, local_file_hash(local_path, algorithm).await?).await?;
+        }
+
+        if options.preserve {
+            apply_metadata(local_path, &metadata)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn ranges(len: u64, chunk_size: u64) -> impl Iterator<Item = Range<u64>> {
+    let chunk_size = chunk_size.max(1);
+    (0..len.div_ceil(chunk_size)).map(move |i| i * chunk_size..((i + 1) * chunk_size).min(len))
+}
+
+// ranges finish out of order, so the hash is taken from the finished local file instead of the stream
+
+async fn local_file_hash(local_path: &Path, algorithm: HashAlgorithm) -> Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update_from(&mut File::open(local_path).await?, u64::MAX).await?;
+    Ok(hasher.finalize())
+}
@@ -0,0 +1,351 @@
+use std::{time::{Duration, SystemTime, UNIX_EPOCH}, path::{Path, PathBuf}, process::ExitStatus, sync::Arc};
+use shell_escape::unix::escape;
+use openssh::{Session, SessionBuilder, KnownHosts, Command, ForwardType, Socket, Stdio};
+use openssh_sftp_client::{Sftp, file::TokioCompatFile, fs::DirEntry};
+use futures::TryStreamExt;
+use std::io::SeekFrom;
+use tokio::{fs::File, io::{copy, AsyncRead, AsyncWrite, AsyncSeekExt, AsyncWriteExt}, time::{timeout, interval, Instant}, net::TcpStream, sync::OnceCell};
+use crate::{error::{Error, Result, RemoteStatus}, hash::{HashAlgorithm, Hasher, HashingReader, sha256_prefix}, limit::{LimitedReader, RateLimiter}, progress::{ProgressCallback, ProgressStream}, sftp::{SftpHandle, apply_metadata}};
+
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    pub keyfile: PathBuf,
+}
+
+/// Whether a transfer may continue from a partial destination file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    #[default]
+    No,
+
+    /// Continue when the partial file is not larger than the source
+    Size,
+
+    /// Additionally compare the sha256 of the partial file with the same prefix of the source
+    Hash,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PutOptions {
+    /// Write to a temporary sibling and rename it over the destination when complete
+    pub atomic: bool,
+
+    /// Continue a partial remote file, only used for file sources and ignored with `atomic`
+    pub resume: Resume,
+
+    /// Hash the data while sending and compare it with the remote file afterwards
+    pub verify: Option<HashAlgorithm>,
+
+    /// Called with the progress of every file sent
+    pub progress: Option<ProgressCallback>,
+
+    /// Caps the bandwidth of all files sent with these options together
+    pub limit: Option<RateLimiter>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GetOptions {
+    /// Copy mode and mtime of the remote file
+    pub preserve: bool,
+
+    /// Continue a partial local file
+    pub resume: Resume,
+
+    /// Hash the data while receiving and compare it with the remote file afterwards
+    pub verify: Option<HashAlgorithm>,
+
+    /// Called with the progress of every file received
+    pub progress: Option<ProgressCallback>,
+
+    /// Caps the bandwidth of all files received with these options together
+    pub limit: Option<RateLimiter>,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    session: Arc<Session>,
+
+    // one sftp channel per connection, opened on first use
+    sftp: OnceCell<SftpHandle>,
+}
+
+impl Client {
+    pub async fn connect(options: ConnectOptions) -> Result<Self> {
+        let session = SessionBuilder::default()
+            .user(options.user.clone())
+            .port(options.port)
+            .keyfile(&options.keyfile)
+            .connect_timeout(Duration::from_secs(10))
+            .known_hosts_check(KnownHosts::Add)
+            .server_alive_interval(Duration::from_secs(60))
+            .connect_mux(&options.host)
+            .await
+            .map_err(|e| Error::connect(&options.host, options.port, e))?;
+
+        Ok(Self { session: Arc::new(session), sftp: OnceCell::new() })
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn command<S: AsRef<str>>(&self, argv: impl IntoIterator<Item = S>) -> Command<'_> {
+
+        // raw_command passes the line to the remote shell as is, so every argument is escaped here
+
+        self.session.raw_command(argv.into_iter().map(|s| escape(s.as_ref().into()).to_string()).collect::<Vec<_>>().join(" "))
+    }
+
+    pub async fn shell(&self) -> Result<()> {
+
+        // the mux protocol used here can't allocate a pty, so this is a line based interactive shell
+
+        let status = self.session.raw_command(r#"exec "${SHELL:-/bin/sh}" -i"#)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .await;
+        check_status("shell", status)
+    }
+
+    pub async fn forward(&self, forward_type: ForwardType, listen: Socket<'_>, connect: Socket<'_>) -> Result<()> {
+        self.session.request_port_forward(forward_type, listen, connect).await?;
+        Ok(())
+    }
+
+    /// The sftp channel shared by every file operation of this client.
+    pub async fn sftp(&self) -> Result<&Sftp> {
+        let handle = self.sftp.get_or_try_init(|| SftpHandle::open(Arc::clone(&self.session))).await?;
+        Ok(handle.sftp())
+    }
+
+    pub async fn close(self) -> Result<()> {
+        if let Some(handle) = self.sftp.into_inner() {
+            handle.close().await?;
+        }
+
+        // the sftp child held the only other reference, so this only fails if it leaked
+        if let Ok(session) = Arc::try_unwrap(self.session) {
+            session.close().await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn put_data_file(&self, remote_path: &Path, data: impl AsyncRead + Unpin) -> Result<()> {
+        self.put_data_file_with(remote_path, data, &PutOptions::default()).await
+    }
+
+    pub async fn put_data_file_with(&self, remote_path: &Path, data: impl AsyncRead + Unpin, options: &PutOptions) -> Result<()> {
+        self.put_stream(remote_path, data, None, options).await
+    }
+
+    /// Uploads `data` of `total` bytes when known, which only matters for progress reports.
+    pub(crate) async fn put_stream(&self, remote_path: &Path, data: impl AsyncRead + Unpin, total: Option<u64>, options: &PutOptions) -> Result<()> {
+        let data = LimitedReader::new(data, options.limit.clone());
+        let data = ProgressStream::new(data, options.progress.clone(), remote_path, 0, total);
+        let mut data = HashingReader::new(data, options.verify.map(Hasher::new));
+        self.put_hashed(remote_path, &mut data, options.atomic).await?;
+
+        // compared after the rename, so this checks what readers of the destination see
+
+        if let (Some(algorithm), Some(local)) = (options.verify, data.finish()) {
+            self.verify_remote_hash(remote_path, algorithm, local).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn put_hashed(&self, remote_path: &Path, mut data: impl AsyncRead + Unpin, atomic: bool) -> Result<()> {
+
+        // example for putting data to remote file
+        // AsyncRead accepts almost types of input stream, or fixed data
+
+        let sftp = self.sftp().await?;
+
+        if !atomic {
+            let remote_file = sftp.create(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+            let mut remote_file = Box::pin(TokioCompatFile::new(remote_file)); // tokio copy requires Unpin
+
+            copy(&mut data, &mut remote_file).await?;
+
+            return Ok(());
+        }
+
+        // write a sibling and rename it over the target, so readers never see a partial file
+
+        let temp_path = temp_path_for(remote_path);
+        let result = async {
+            let remote_file = sftp.create(&temp_path).await.map_err(|e| Error::sftp_at(&temp_path, e))?;
+            let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+
+            copy(&mut data, &mut remote_file).await?;
+            if sftp.support_fsync() {
+                remote_file.as_mut().as_mut_file().sync_all().await?;
+            }
+            drop(remote_file);
+
+            let mut fs = sftp.fs();
+            if let Some(permissions) = fs.metadata(remote_path).await.ok().and_then(|metadata| metadata.permissions()) {
+                fs.set_permissions(&temp_path, permissions).await?;
+            }
+            fs.rename(&temp_path, remote_path).await?;
+
+            Ok(())
+        }.await;
+
+        if result.is_err() {
+            let _ = sftp.fs().remove_file(&temp_path).await;
+        }
+
+        result
+    }
+
+    pub async fn get_data_file(&self, remote_path: &Path, out: impl AsyncWrite + Unpin) -> Result<()> {
+        self.get_data_file_with(remote_path, out, &GetOptions::default()).await
+    }
+
+    /// Streams a remote file into `out`, `preserve` and `resume` don't apply to a stream.
+    pub async fn get_data_file_with(&self, remote_path: &Path, mut out: impl AsyncWrite + Unpin, options: &GetOptions) -> Result<()> {
+        let sftp = self.sftp().await?;
+
+        let mut remote_file = sftp.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let total = remote_file.metadata().await?.len();
+        let remote_file = Box::pin(TokioCompatFile::new(remote_file));
+        let remote_file = LimitedReader::new(remote_file, options.limit.clone());
+        let remote_file = ProgressStream::new(remote_file, options.progress.clone(), remote_path, 0, total);
+        let mut remote_file = HashingReader::new(remote_file, options.verify.map(Hasher::new));
+
+        copy(&mut remote_file, &mut out).await?;
+
+        if let (Some(algorithm), Some(local)) = (options.verify, remote_file.finish()) {
+            self.verify_remote_hash(remote_path, algorithm, local).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads a remote file, optionally copying its mode and mtime onto the local copy.
+    pub async fn get_file(&self, remote_path: &Path, local_path: &Path, options: &GetOptions) -> Result<()> {
+        let sftp = self.sftp().await?;
+
+        let mut remote_file = sftp.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let metadata = remote_file.metadata().await?;
+
+        let partial_len = match tokio::fs::metadata(local_path).await {
+            Ok(local) if local.is_file() => local.len(),
+            _ => 0,
+        };
+        let offset = self.resume_offset(options.resume, local_path, remote_path, partial_len, metadata.len().unwrap_or(0)).await?;
+
+        let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+        remote_file.seek(SeekFrom::Start(offset)).await?;
+
+        // the kept prefix is part of the file too, so it goes into the hash first
+
+        let mut hasher = options.verify.map(Hasher::new);
+        if let Some(hasher) = hasher.as_mut().filter(|_| offset > 0) {
+            hasher.update_from(&mut File::open(local_path).await?, offset).await?;
+        }
+        let remote_file = LimitedReader::new(remote_file, options.limit.clone());
+        let remote_file = ProgressStream::new(remote_file, options.progress.clone(), remote_path, offset, metadata.len());
+        let mut remote_file = HashingReader::new(remote_file, hasher);
+
+        let mut local_file = File::options().write(true).create(true).truncate(offset == 0).open(local_path).await?;
+        local_file.seek(SeekFrom::Start(offset)).await?;
+        let copied = copy(&mut remote_file, &mut local_file).await?;
+
+        // a longer partial file that was kept must not leave a stale tail
+        local_file.set_len(offset + copied).await?;
+        local_file.flush().await?;
+        drop(local_file);
+
+        if let (Some(algorithm), Some(local)) = (options.verify, remote_file.finish()) {
+            self.verify_remote_hash(remote_path, algorithm, local).await?;
+        }
+
+        if options.preserve {
+            apply_metadata(local_path, &metadata)?;
+        }
+
+        Ok(())
+    }
+
+    /// How many bytes of a partial destination can be kept, 0 to start over.
+    ///
+    /// The prefix compared is always the one in the local file against the remote one.
+    pub(crate) async fn resume_offset(&self, resume: Resume, local_path: &Path, remote_path: &Path, partial_len: u64, source_len: u64) -> Result<u64> {
+        if resume == Resume::No || partial_len == 0 || partial_len > source_len {
+            return Ok(0);
+        }
+        if resume == Resume::Hash {
+            let local_hash = sha256_prefix(&mut File::open(local_path).await?, partial_len).await?;
+            if local_hash != self.remote_sha256_prefix(remote_path, partial_len).await? {
+                return Ok(0);
+            }
+        }
+        Ok(partial_len)
+    }
+
+    pub async fn read_dir(&self, remote_path: &Path) -> Result<Vec<DirEntry>> {
+        let sftp = self.sftp().await?;
+
+        let dir = sftp.fs().open_dir(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let entries = dir.read_dir().try_collect().await.map_err(|e| Error::sftp_at(remote_path, e))?;
+
+        Ok(entries)
+    }
+
+    pub async fn remove_file(&self, remote_path: &Path) -> Result<()> {
+        let sftp = self.sftp().await?;
+
+        sftp.fs().remove_file(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+
+        Ok(())
+    }
+}
+
+pub async fn wait_for_ssh_connectable(host: &str, port: u16, max_wait: Option<Duration>) -> Result<()> {
+
+    // lightweight ssh connection check than connect
+
+    let deadline = max_wait.map(|max_wait| Instant::now() + max_wait);
+    let mut interval = interval(Duration::from_secs(10));
+
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::ConnectTimeout { host: host.to_string(), port });
+        }
+        match timeout(Duration::from_secs(5), TcpStream::connect((host, port))).await {
+            Err(_) => {
+                eprintln!("waiting for ssh: timeout");
+                interval.tick().await;
+            }
+            Ok(Err(e)) => {
+                eprintln!("waiting for ssh: {}", e);
+                interval.tick().await;
+            }
+            Ok(Ok(_)) => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub(crate) fn temp_path_for(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.subsec_nanos());
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), nanos))
+}
+
+pub(crate) fn check_status(command: &str, status: Result<ExitStatus, openssh::Error>) -> Result<()> {
+    let status = RemoteStatus::from_wait(status)?;
+    if status.success() {
+        return Ok(());
+    }
+    Err(Error::RemoteCommand { command: command.to_string(), status })
+}
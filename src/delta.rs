@@ -0,0 +1,421 @@
+use std::{collections::HashMap, io::SeekFrom, num::NonZeroU64, path::Path};
+use openssh_sftp_client::file::{File as SftpFile, TokioCompatFile};
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::{copy, AsyncRead, AsyncReadExt, AsyncSeekExt}};
+use crate::{client::{Client, PutOptions, temp_path_for}, error::{Error, Result}, hash::{Hasher, HashingReader}, limit::LimitedReader};
+
+// adler-32, which python's zlib computes remotely at C speed and which can be rolled locally
+
+const MOD_ADLER: u32 = 65521;
+
+// per block of the file: adler-32 and sha256, one line each; exits 127 without python3 like a missing tool would
+const SIGNATURE_SCRIPT: &str = r#"import sys, zlib, hashlib
+f = open(sys.argv[1], "rb")
+n = int(sys.argv[2])
+out = sys.stdout
+while True:
+    b = f.read(n)
+    if not b:
+        break
+    out.write("%d %s\n" % (zlib.adler32(b), hashlib.sha256(b).hexdigest()))
+"#;
+
+/// How [`Client::put_file_delta`] splits files into blocks.
+#[derive(Debug, Default, Clone)]
+pub struct DeltaOptions {
+    /// Bytes per block, by default about the square root of the file size
+    pub block_size: Option<usize>,
+}
+
+/// How much of a delta upload was sent and how much was reused from the remote file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeltaReport {
+    pub sent: u64,
+    pub matched: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// A block of the old remote file
+    Copy { block: u64, offset: u64 },
+
+    /// Bytes at the same offset in the local file
+    Literal { offset: u64, len: u64 },
+}
+
+#[derive(Debug)]
+struct Signature {
+    strong: [u8; 32],
+    block: u64,
+}
+
+impl Client {
+    /// Uploads a modified file by sending only the blocks that differ from the remote copy, like rsync.
+    ///
+    /// Block signatures are computed remotely by python3 when available, otherwise the remote file is
+    /// read back. When every unchanged block stays at its offset the remote file is patched in place,
+    /// else it is rebuilt in a temporary sibling with the sftp copy-data extension. Without that
+    /// extension, or without a remote file to start from, the whole file is uploaded. Only `verify`
+    /// and `limit` of `options` apply to the delta itself, the limit to the literal data sent.
+    pub async fn put_file_delta(&self, local_path: &Path, remote_path: &Path, options: &PutOptions, delta: &DeltaOptions) -> Result<DeltaReport> {
+        let len = tokio::fs::metadata(local_path).await?.len();
+        let sftp = self.sftp().await?;
+
+        let Ok(remote_len) = sftp.fs().metadata(remote_path).await.map(|metadata| metadata.len().unwrap_or(0)) else {
+            self.put_file(local_path, remote_path, options).await?;
+            return Ok(DeltaReport { sent: len, matched: 0 });
+        };
+
+        let block_size = delta.block_size.unwrap_or_else(|| default_block_size(len.max(remote_len))).max(1);
+        let signatures = self.remote_signatures(remote_path, block_size).await?;
+
+        let local_file = File::open(local_path).await?;
+        let mut local_file = HashingReader::new(local_file, options.verify.map(Hasher::new));
+        let ops = scan(&mut local_file, block_size, &signatures).await?;
+        let local_hash = local_file.finish();
+
+        let in_place = ops.iter().all(|op| match *op {
+            Op::Copy { block, offset } => block * block_size as u64 == offset,
+            Op::Literal { .. } => true,
+        });
+        if !in_place && !sftp.support_copy() {
+            self.put_file(local_path, remote_path, options).await?;
+            return Ok(DeltaReport { sent: len, matched: 0 });
+        }
+
+        let report = if in_place {
+            self.patch_in_place(local_path, remote_path, len, &ops, options).await?
+        } else {
+            self.rebuild(local_path, remote_path, block_size, &ops, options).await?
+        };
+
+        if let (Some(algorithm), Some(local)) = (options.verify, local_hash) {
+            self.verify_remote_hash(remote_path, algorithm, local).await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn remote_signatures(&self, remote_path: &Path, block_size: usize) -> Result<HashMap<u32, Vec<Signature>>> {
+        let path = remote_path.to_string_lossy();
+        let block_size_arg = block_size.to_string();
+        let output = self.command(["python3", "-c", SIGNATURE_SCRIPT, &path, &block_size_arg]).output().await?;
+
+        let mut signatures = HashMap::<u32, Vec<Signature>>::new();
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            for (block, line) in stdout.lines().enumerate() {
+                let parsed = line.split_once(' ').and_then(|(weak, strong)| Some((weak.parse().ok()?, from_hex(strong)?)));
+                let Some((weak, strong)) = parsed else {
+                    return Err(Error::parse("block signature", line));
+                };
+                signatures.entry(weak).or_default().push(Signature { strong, block: block as u64 });
+            }
+            return Ok(signatures);
+        }
+
+        // no python3 remotely, the signatures cost a full read of the remote file instead
+
+        let remote_file = self.sftp().await?.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+        let mut buf = vec![0; block_size];
+        for block in 0.. {
+            let n = read_full(&mut remote_file, &mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            signatures.entry(adler32(&buf[..n])).or_default().push(Signature { strong: Sha256::digest(&buf[..n]).into(), block });
+        }
+        Ok(signatures)
+    }
+
+    async fn patch_in_place(&self, local_path: &Path, remote_path: &Path, len: u64, ops: &[Op], options: &PutOptions) -> Result<DeltaReport> {
+        let remote_file = self.sftp().await?.options().write(true).open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+        let mut local_file = File::open(local_path).await?;
+        let mut report = DeltaReport::default();
+
+        for op in ops {
+            match *op {
+                Op::Copy { .. } => {}
+                Op::Literal { offset, len } => {
+                    local_file.seek(SeekFrom::Start(offset)).await?;
+                    remote_file.seek(SeekFrom::Start(offset)).await?;
+                    copy(&mut LimitedReader::new((&mut local_file).take(len), options.limit.clone()), &mut remote_file).await?;
+                    report.sent += len;
+                }
+            }
+        }
+        remote_file.as_mut().as_mut_file().set_len(len).await?;
+        report.matched = len - report.sent;
+
+        Ok(report)
+    }
+
+    async fn rebuild(&self, local_path: &Path, remote_path: &Path, block_size: usize, ops: &[Op], options: &PutOptions) -> Result<DeltaReport> {
+        let sftp = self.sftp().await?;
+        let temp_path = temp_path_for(remote_path);
+        let mut report = DeltaReport::default();
+
+        let result = async {
+            let mut old = sftp.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+            let old_len = old.metadata().await?.len().unwrap_or(0);
+            let mut new = sftp.create(&temp_path).await.map_err(|e| Error::sftp_at(&temp_path, e))?;
+            let mut local_file = File::open(local_path).await?;
+
+            for op in ops {
+                match *op {
+                    Op::Copy { block, offset } => {
+                        seek(&mut old, block * block_size as u64).await?;
+                        seek(&mut new, offset).await?;
+                        let len = old_len.saturating_sub(block * block_size as u64).min(block_size as u64);
+                        if let Some(len) = NonZeroU64::new(len) {
+                            old.copy_to(&mut new, len).await?;
+                            report.matched += len.get();
+                        }
+                    }
+                    Op::Literal { offset, len } => {
+                        local_file.seek(SeekFrom::Start(offset)).await?;
+                        seek(&mut new, offset).await?;
+                        let mut new_writer = Box::pin(TokioCompatFile::new(new.clone()));
+                        copy(&mut LimitedReader::new((&mut local_file).take(len), options.limit.clone()), &mut new_writer).await?;
+                        report.sent += len;
+                    }
+                }
+            }
+            if sftp.support_fsync() {
+                new.sync_all().await?;
+            }
+            drop(new);
+
+            let mut fs = sftp.fs();
+            if let Some(permissions) = fs.metadata(remote_path).await.ok().and_then(|metadata| metadata.permissions()) {
+                fs.set_permissions(&temp_path, permissions).await?;
+            }
+            fs.rename(&temp_path, remote_path).await?;
+
+            Ok::<_, Error>(())
+        }.await;
+
+        if result.is_err() {
+            let _ = sftp.fs().remove_file(&temp_path).await;
+        }
+        result.map(|()| report)
+    }
+}
+
+// matches every full block sized window of the local file against the remote blocks, rsync's algorithm
+
+async fn scan(reader: &mut (impl AsyncRead + Unpin), block_size: usize, signatures: &HashMap<u32, Vec<Signature>>) -> Result<Vec<Op>> {
+    let mut ops = Vec::new();
+    let mut buf = Vec::new();
+    let mut start = 0; // window start in buf
+    let mut base = 0u64; // file offset of buf[0]
+    let mut literal_start = 0u64;
+    let mut eof = false;
+    let mut rolling = None;
+
+    loop {
+
+        // a window plus the byte that slides in next, dropping what is behind the window
+
+        if !eof && buf.len() - start <= block_size {
+            buf.drain(..start);
+            base += start as u64;
+            start = 0;
+            let filled = buf.len();
+            buf.resize(block_size * 4, 0);
+            let n = read_full(reader, &mut buf[filled..]).await?;
+            buf.truncate(filled + n);
+            eof = filled + n < block_size * 4;
+        }
+        if buf.len() - start < block_size {
+            break;
+        }
+
+        let window = &buf[start..start + block_size];
+        let (a, b) = rolling.unwrap_or_else(|| adler_parts(window));
+        let offset = base + start as u64;
+
+        // identical blocks are common (zeroed regions of images), the one already at this offset wins so the file can be patched in place
+        let matched = signatures.get(&(b << 16 | a)).and_then(|candidates| {
+            let strong: [u8; 32] = Sha256::digest(window).into();
+            let mut equal = candidates.iter().filter(|candidate| candidate.strong == strong);
+            let first = equal.next()?;
+            Some(std::iter::once(first).chain(equal).find(|candidate| candidate.block * block_size as u64 == offset).unwrap_or(first))
+        });
+
+        if let Some(signature) = matched {
+            if offset > literal_start {
+                ops.push(Op::Literal { offset: literal_start, len: offset - literal_start });
+            }
+            ops.push(Op::Copy { block: signature.block, offset });
+            start += block_size;
+            literal_start = offset + block_size as u64;
+            rolling = None;
+            continue;
+        }
+        if buf.len() - start == block_size {
+            break;
+        }
+
+        rolling = Some(roll((a, b), buf[start], buf[start + block_size], block_size));
+        start += 1;
+    }
+
+    // whatever is left, including a tail shorter than a block, goes as literal data
+
+    let end = base + buf.len() as u64;
+    if end > literal_start {
+        ops.push(Op::Literal { offset: literal_start, len: end - literal_start });
+    }
+    Ok(ops)
+}
+
+fn adler_parts(data: &[u8]) -> (u32, u32) {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (a, b)
+}
+
+// slides a block sized window one byte on, dropping `out` and taking in `incoming`
+
+fn roll((a, b): (u32, u32), out: u8, incoming: u8, block_size: usize) -> (u32, u32) {
+    let (out, incoming) = (out as u32, incoming as u32);
+    let a = (a + MOD_ADLER - out + incoming) % MOD_ADLER;
+    let b = (b + 2 * MOD_ADLER - (block_size as u32 % MOD_ADLER) * out % MOD_ADLER + a - 1) % MOD_ADLER;
+    (a, b)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = adler_parts(data);
+    b << 16 | a
+}
+
+fn default_block_size(len: u64) -> usize {
+    ((len as f64).sqrt() as usize).next_multiple_of(1024).clamp(4 << 10, 1 << 20)
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    let mut bytes = [0; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+async fn read_full(reader: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+async fn seek(file: &mut SftpFile, offset: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // deterministic bytes without repeating blocks
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn signatures(old: &[u8], block_size: usize) -> HashMap<u32, Vec<Signature>> {
+        let mut signatures = HashMap::<u32, Vec<Signature>>::new();
+        for (block, chunk) in old.chunks(block_size).enumerate() {
+            signatures.entry(adler32(chunk)).or_default().push(Signature { strong: Sha256::digest(chunk).into(), block: block as u64 });
+        }
+        signatures
+    }
+
+    async fn ops(old: &[u8], new: &[u8], block_size: usize) -> Vec<Op> {
+        scan(&mut &new[..], block_size, &signatures(old, block_size)).await.unwrap()
+    }
+
+    #[test]
+    fn rolled_sums_match_each_window() {
+        let data = data(4096, 7);
+        for block_size in [1, 16, 333, 1024] {
+            let mut rolling = adler_parts(&data[..block_size]);
+            for start in 1..=data.len() - block_size {
+                rolling = roll(rolling, data[start - 1], data[start - 1 + block_size], block_size);
+                assert_eq!(rolling, adler_parts(&data[start..start + block_size]), "block size {block_size}, offset {start}");
+            }
+        }
+
+        // the extremes of the modular arithmetic
+        let ones = vec![0xff; 6000];
+        let rolled = (0..1000).fold(adler_parts(&ones[..5000]), |sums, i| roll(sums, ones[i], ones[i + 5000], 5000));
+        assert_eq!(rolled, adler_parts(&ones[1000..]));
+    }
+
+    #[tokio::test]
+    async fn unchanged_file_copies_in_place() {
+        let old = data(16 * 10, 1);
+        let expected = (0..10).map(|block| Op::Copy { block, offset: block * 16 }).collect::<Vec<_>>();
+        assert_eq!(ops(&old, &old, 16).await, expected);
+    }
+
+    #[tokio::test]
+    async fn inserted_byte_shifts_later_blocks() {
+        let old = data(16 * 4, 2);
+        let mut new = old.clone();
+        new.insert(20, 0x42);
+
+        assert_eq!(ops(&old, &new, 16).await, [
+            Op::Copy { block: 0, offset: 0 },
+            Op::Literal { offset: 16, len: 17 },
+            Op::Copy { block: 2, offset: 33 },
+            Op::Copy { block: 3, offset: 49 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn short_tail_is_literal() {
+        let old = data(16 * 2, 3);
+        let mut new = old.clone();
+        new.extend_from_slice(b"tail!");
+
+        assert_eq!(ops(&old, &new, 16).await, [
+            Op::Copy { block: 0, offset: 0 },
+            Op::Copy { block: 1, offset: 16 },
+            Op::Literal { offset: 32, len: 5 },
+        ]);
+        assert_eq!(ops(&old, b"tiny", 16).await, [Op::Literal { offset: 0, len: 4 }]);
+    }
+
+    #[tokio::test]
+    async fn equal_blocks_prefer_their_own_offset() {
+        let block = data(16, 4);
+        let old = [block.clone(), block.clone(), block.clone()].concat();
+        let expected = (0..3).map(|block| Op::Copy { block, offset: block * 16 }).collect::<Vec<_>>();
+        assert_eq!(ops(&old, &old, 16).await, expected);
+    }
+
+    #[test]
+    fn default_block_size_is_clamped() {
+        assert_eq!(default_block_size(0), 4 << 10);
+        assert_eq!(default_block_size(100_000_000), 10 << 10);
+        assert_eq!(default_block_size(1 << 50), 1 << 20);
+    }
+}
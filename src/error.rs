@@ -0,0 +1,180 @@
+use std::{io, fmt, path::{Path, PathBuf}};
+use openssh_sftp_client::error::SftpErrorKind;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the library.
+///
+/// Every variant maps to a stable process exit code (see [`Error::exit_code`]),
+/// so scripts driving the binary can branch on the kind of failure.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The ssh port did not become reachable in time, or ssh itself timed out. Exit code 10.
+    #[error("timed out connecting to {host}:{port}")]
+    ConnectTimeout { host: String, port: u16 },
+
+    /// The server rejected our credentials. Exit code 11.
+    #[error("authentication failed")]
+    AuthFailed(#[source] openssh::Error),
+
+    /// The server host key does not match known_hosts. Exit code 12.
+    #[error("host key verification failed")]
+    HostKeyMismatch(#[source] openssh::Error),
+
+    /// Any other ssh or connection failure. Exit code 13.
+    #[error("ssh failed")]
+    Ssh(#[source] openssh::Error),
+
+    /// A remote command ran but did not exit successfully. Exit code 14.
+    #[error("remote command `{command}` failed: {status}")]
+    RemoteCommand { command: String, status: RemoteStatus },
+
+    /// The sftp subsystem or an sftp request failed. Exit code 15.
+    #[error("sftp failed")]
+    Sftp(#[from] openssh_sftp_client::Error),
+
+    /// Output from the remote side could not be understood. Exit code 16.
+    #[error("failed to parse {what}: {input:?}")]
+    Parse { what: &'static str, input: String },
+
+    /// Local I/O failure. Exit code 17.
+    #[error("i/o error")]
+    Io(#[from] io::Error),
+
+    /// Remote processes were still running when we gave up waiting. Exit code 18.
+    #[error("processes still running: {pids:?}")]
+    WaitTimeout { pids: Vec<u32> },
+
+    /// The transferred file hashes differently on the two ends. Exit code 19.
+    #[error("checksum mismatch for {}: local {local}, remote {remote}", path.display())]
+    ChecksumMismatch { path: PathBuf, local: String, remote: String },
+
+    /// A remote path does not exist. Exit code 20.
+    #[error("no such remote file: {}", path.display())]
+    NotFound { path: PathBuf, #[source] source: openssh_sftp_client::Error },
+
+    /// The server refused access to a remote path. Exit code 21.
+    #[error("permission denied: {}", path.display())]
+    PermissionDenied { path: PathBuf, #[source] source: openssh_sftp_client::Error },
+}
+
+/// How a remote command terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteStatus {
+    Code(i32),
+
+    /// An exit code of 129 to 192 read as 128 + signal number, the shell convention. This is a guess:
+    /// a command that calls `exit 130` itself looks exactly like one killed by SIGINT.
+    Signal(i32),
+
+    // the mux protocol reports a signal death without telling which one
+    Terminated,
+}
+
+impl RemoteStatus {
+    pub(crate) fn from_wait(status: Result<std::process::ExitStatus, openssh::Error>) -> Result<Self> {
+        match status {
+            Ok(status) => Ok(status.code().map_or(Self::Terminated, Self::Code)),
+            Err(openssh::Error::RemoteProcessTerminated) => Ok(Self::Terminated),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        *self == Self::Code(0)
+    }
+
+    /// Exit code a local shell would report for the same termination.
+    pub fn exit_code(&self) -> u8 {
+        match *self {
+            Self::Code(code) => code as u8,
+            Self::Signal(signal) => 128u8.wrapping_add(signal as u8),
+            Self::Terminated => 255,
+        }
+    }
+}
+
+impl fmt::Display for RemoteStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "exit code {}", code),
+            Self::Signal(signal) => write!(f, "signal {}", signal),
+            Self::Terminated => write!(f, "terminated"),
+        }
+    }
+}
+
+impl Error {
+    /// Process exit code for this error.
+    ///
+    /// | code | meaning |
+    /// |------|---------|
+    /// | 10   | connect timeout |
+    /// | 11   | authentication failure |
+    /// | 12   | host key mismatch |
+    /// | 13   | other ssh failure |
+    /// | 14   | remote command exited non-zero |
+    /// | 15   | sftp failure |
+    /// | 16   | parse failure |
+    /// | 17   | local i/o failure |
+    /// | 18   | processes still running after waiting |
+    /// | 19   | checksum mismatch after transfer |
+    /// | 20   | remote file not found |
+    /// | 21   | remote permission denied |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::ConnectTimeout { .. } => 10,
+            Self::AuthFailed(_) => 11,
+            Self::HostKeyMismatch(_) => 12,
+            Self::Ssh(_) => 13,
+            Self::RemoteCommand { .. } => 14,
+            Self::Sftp(_) => 15,
+            Self::Parse { .. } => 16,
+            Self::Io(_) => 17,
+            Self::WaitTimeout { .. } => 18,
+            Self::ChecksumMismatch { .. } => 19,
+            Self::NotFound { .. } => 20,
+            Self::PermissionDenied { .. } => 21,
+        }
+    }
+
+    /// Attaches the path to sftp failures that are about the path itself.
+    pub(crate) fn sftp_at(path: &Path, err: openssh_sftp_client::Error) -> Self {
+        match err {
+            openssh_sftp_client::Error::SftpError(SftpErrorKind::NoSuchFile, _) => Self::NotFound { path: path.to_path_buf(), source: err },
+            openssh_sftp_client::Error::SftpError(SftpErrorKind::PermDenied, _) => Self::PermissionDenied { path: path.to_path_buf(), source: err },
+            err => Self::Sftp(err),
+        }
+    }
+
+    pub(crate) fn parse(what: &'static str, input: impl Into<String>) -> Self {
+        Self::Parse { what, input: input.into() }
+    }
+
+    pub(crate) fn connect(host: &str, port: u16, err: openssh::Error) -> Self {
+
+        // openssh only gives us the text ssh printed on stderr, so classify by message
+
+        let openssh::Error::Connect(e) = &err else {
+            return Self::Ssh(err);
+        };
+        if e.kind() == io::ErrorKind::TimedOut {
+            return Self::ConnectTimeout { host: host.to_string(), port };
+        }
+
+        let message = e.to_string();
+        if message.contains("Host key verification failed") || message.contains("REMOTE HOST IDENTIFICATION HAS CHANGED") {
+            Self::HostKeyMismatch(err)
+        } else if message.contains("Permission denied") || message.contains("Too many authentication failures") {
+            Self::AuthFailed(err)
+        } else {
+            Self::Ssh(err)
+        }
+    }
+}
+
+impl From<openssh::Error> for Error {
+    fn from(err: openssh::Error) -> Self {
+        Self::Ssh(err)
+    }
+}
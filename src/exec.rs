@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use openssh::Stdio;
+use tokio::{fs::File, io::{copy, AsyncReadExt, AsyncWriteExt}};
+use crate::{client::Client, error::{Result, RemoteStatus}};
+
+#[derive(Debug, Default, Clone)]
+pub struct ExecOptions {
+    /// Feed this local file to the remote stdin instead of our own stdin
+    pub stdin_file: Option<PathBuf>,
+
+    /// Collect stdout and stderr into [`ExecOutput`] instead of streaming them to ours
+    pub capture: bool,
+}
+
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub status: RemoteStatus,
+
+    // both are empty unless ExecOptions::capture is set
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Client {
+    /// Runs `argv` on the remote host and reports how it exited.
+    ///
+    /// A non-zero exit is not an error here, the caller decides what to do with `status`. Exit codes
+    /// above 128 come back as [`RemoteStatus::Signal`], which can't be told apart from a command
+    /// exiting with such a code on purpose; [`RemoteStatus::exit_code`] is the same either way.
+    pub async fn exec<S: AsRef<str>>(&self, argv: &[S], options: &ExecOptions) -> Result<ExecOutput> {
+
+        // the mux protocol drops the signal number of a killed process, so let a remote sh
+        // outlive the command and turn its fate into the usual 128 + signal exit code
+
+        let wrapped = ["sh", "-c", r#""$@"; exit $?"#, "sh"].into_iter()
+            .chain(argv.iter().map(AsRef::as_ref));
+
+        let (stdout, stderr) = if options.capture {
+            (Stdio::piped(), Stdio::piped())
+        } else {
+            (Stdio::inherit(), Stdio::inherit())
+        };
+        let stdin = if options.stdin_file.is_some() { Stdio::piped() } else { Stdio::inherit() };
+
+        let mut child = self.command(wrapped)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .await?;
+
+        let child_stdin = child.stdin().take();
+        let child_stdout = child.stdout().take();
+        let child_stderr = child.stderr().take();
+
+        let feed = async {
+            if let (Some(path), Some(mut child_stdin)) = (&options.stdin_file, child_stdin) {
+                copy(&mut File::open(path).await?, &mut child_stdin).await?;
+                child_stdin.shutdown().await?;
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        let read_stdout = async {
+            let mut buf = Vec::new();
+            if let Some(mut child_stdout) = child_stdout {
+                child_stdout.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+        let read_stderr = async {
+            let mut buf = Vec::new();
+            if let Some(mut child_stderr) = child_stderr {
+                child_stderr.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+
+        let ((), stdout, stderr) = tokio::try_join!(feed, read_stdout, read_stderr)?;
+
+        let status = match RemoteStatus::from_wait(child.wait().await)? {
+            RemoteStatus::Code(code) if (129..=192).contains(&code) => RemoteStatus::Signal(code - 128),
+            status => status,
+        };
+
+        Ok(ExecOutput { status, stdout, stderr })
+    }
+}
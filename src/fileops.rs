@@ -0,0 +1,296 @@
+use std::{io, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use futures::{Stream, TryStreamExt, stream};
+use openssh_sftp_client::{UnixTimeStamp, file::TokioCompatFile, metadata::{MetaData, MetaDataBuilder}};
+use serde::{Serialize, Serializer};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use crate::{client::Client, error::{Error, Result}, glob::Glob, sftp::{mode_of, permissions_from_mode}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Unknown,
+}
+
+impl FileKind {
+    fn of(metadata: &MetaData) -> Self {
+        match metadata.file_type() {
+            Some(t) if t.is_file() => Self::File,
+            Some(t) if t.is_dir() => Self::Dir,
+            Some(t) if t.is_symlink() => Self::Symlink,
+            Some(t) if t.is_fifo() => Self::Fifo,
+            Some(t) if t.is_socket() => Self::Socket,
+            Some(t) if t.is_block_device() => Self::BlockDevice,
+            Some(t) if t.is_char_device() => Self::CharDevice,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Metadata of a remote path, each field as far as the server reported it.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStat {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub len: Option<u64>,
+
+    /// Permission bits only, without the file type
+    pub mode: Option<u32>,
+
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+
+    #[serde(serialize_with = "unix_seconds")]
+    pub accessed: Option<SystemTime>,
+
+    #[serde(serialize_with = "unix_seconds")]
+    pub modified: Option<SystemTime>,
+
+    /// Where a symlink points, only filled in by [`Client::lstat`]
+    pub target: Option<PathBuf>,
+}
+
+impl FileStat {
+    pub(crate) fn new(path: &Path, metadata: &MetaData) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            kind: FileKind::of(metadata),
+            len: metadata.len(),
+            mode: metadata.permissions().map(|permissions| mode_of(&permissions)),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            accessed: metadata.accessed().map(|time| time.as_system_time()),
+            modified: metadata.modified().map(|time| time.as_system_time()),
+            target: None,
+        }
+    }
+}
+
+// seconds since the epoch, which is all sftp v3 transfers anyway
+
+fn unix_seconds<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    time.map(|time| time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())).serialize(serializer)
+}
+
+impl Client {
+    /// Metadata of a remote path, following symlinks.
+    pub async fn stat(&self, remote_path: &Path) -> Result<FileStat> {
+        let metadata = self.sftp().await?.fs().metadata(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        Ok(FileStat::new(remote_path, &metadata))
+    }
+
+    /// Metadata of a remote path itself, with the target when it is a symlink.
+    pub async fn lstat(&self, remote_path: &Path) -> Result<FileStat> {
+        let mut fs = self.sftp().await?.fs();
+        let metadata = fs.symlink_metadata(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let mut stat = FileStat::new(remote_path, &metadata);
+        if stat.kind == FileKind::Symlink {
+            stat.target = Some(fs.read_link(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?);
+        }
+        Ok(stat)
+    }
+
+    pub async fn chmod(&self, remote_path: &Path, mode: u32) -> Result<()> {
+        self.sftp().await?.fs().set_permissions(remote_path, permissions_from_mode(mode)).await.map_err(|e| Error::sftp_at(remote_path, e))
+    }
+
+    /// Changes owner and group by number, sftp has no notion of user names. `None` keeps the current one.
+    pub async fn chown(&self, remote_path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        let mut fs = self.sftp().await?.fs();
+
+        // the protocol sets both ids at once
+
+        let (uid, gid) = match (uid, gid) {
+            (Some(uid), Some(gid)) => (uid, gid),
+            _ => {
+                let metadata = fs.metadata(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+                match (uid.or(metadata.uid()), gid.or(metadata.gid())) {
+                    (Some(uid), Some(gid)) => (uid, gid),
+                    _ => return Err(Error::parse("owner of", remote_path.to_string_lossy())),
+                }
+            }
+        };
+        let mut metadata = MetaDataBuilder::new();
+        metadata.id((uid, gid));
+        fs.set_metadata(remote_path, metadata.create()).await.map_err(|e| Error::sftp_at(remote_path, e))
+    }
+
+    /// Sets the mtime of a remote path, in whole seconds. The protocol sets both times at once, so the
+    /// access time becomes now.
+    pub async fn set_modified(&self, remote_path: &Path, modified: SystemTime) -> Result<()> {
+        let timestamp = |time| UnixTimeStamp::new(time).map_err(io::Error::other);
+        let mut metadata = MetaDataBuilder::new();
+        metadata.time(timestamp(SystemTime::now())?, timestamp(modified)?);
+        self.sftp().await?.fs().set_metadata(remote_path, metadata.create()).await.map_err(|e| Error::sftp_at(remote_path, e))
+    }
+
+    /// Renames atomically where the server supports posix-rename, replacing an existing destination.
+    pub async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.sftp().await?.fs().rename(from, to).await.map_err(|e| Error::sftp_at(from, e))
+    }
+
+    /// Creates a symlink at `link` pointing to `target`, which is stored as given.
+    pub async fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.sftp().await?.fs().symlink(target, link).await.map_err(|e| Error::sftp_at(link, e))
+    }
+
+    /// Creates a directory, or with `parents` the directory and its missing parents like `mkdir -p`.
+    pub async fn mkdir(&self, remote_dir: &Path, parents: bool, mode: Option<u32>) -> Result<()> {
+        if parents {
+            self.create_dir_all(remote_dir).await?;
+        } else {
+            self.sftp().await?.fs().create_dir(remote_dir).await.map_err(|e| Error::sftp_at(remote_dir, e))?;
+        }
+        if let Some(mode) = mode {
+            self.chmod(remote_dir, mode).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes a file, symlink or empty directory, or with `recursive` a whole tree like `rm -r`.
+    ///
+    /// Symlinks are removed, never followed.
+    pub async fn remove(&self, remote_path: &Path, recursive: bool) -> Result<()> {
+        let mut fs = self.sftp().await?.fs();
+        let metadata = fs.symlink_metadata(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        if FileKind::of(&metadata) != FileKind::Dir {
+            return fs.remove_file(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e));
+        }
+        if !recursive {
+            return fs.remove_dir(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e));
+        }
+
+        // directories are emptied on the way down and removed deepest first on the way back
+
+        let mut pending = vec![remote_path.to_path_buf()];
+        let mut dirs = Vec::new();
+        while let Some(dir) = pending.pop() {
+            let entries = fs.open_dir(&dir).await.map_err(|e| Error::sftp_at(&dir, e))?
+                .read_dir()
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| Error::sftp_at(&dir, e))?;
+            for entry in entries {
+                let name = entry.filename();
+                if name == Path::new(".") || name == Path::new("..") {
+                    continue;
+                }
+                let path = dir.join(name);
+                if entry.file_type().is_some_and(|file_type| file_type.is_dir()) {
+                    pending.push(path);
+                } else {
+                    fs.remove_file(&path).await.map_err(|e| Error::sftp_at(&path, e))?;
+                }
+            }
+            dirs.push(dir);
+        }
+        for dir in dirs.iter().rev() {
+            fs.remove_dir(dir).await.map_err(|e| Error::sftp_at(dir, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Expands a shell style pattern against the remote tree, one path component at a time.
+    ///
+    /// Wildcards don't match a leading `.` unless the pattern component starts with one. A pattern
+    /// matching nothing comes back as is, so the caller trips over it like a shell would.
+    pub async fn expand_glob(&self, pattern: &Path) -> Result<Vec<PathBuf>> {
+        let mut fs = self.sftp().await?.fs();
+        let mut paths = vec![PathBuf::new()];
+
+        for component in pattern.components() {
+            let name = component.as_os_str().to_string_lossy();
+            let glob = Glob::new(&name)?;
+            if glob.is_literal() {
+                paths.iter_mut().for_each(|path| path.push(component));
+                continue;
+            }
+
+            let mut matched = Vec::new();
+            for dir in &paths {
+                let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir.as_path() };
+
+                // directories that can't be listed simply match nothing, like in a shell
+                let Ok(handle) = fs.open_dir(listed).await else {
+                    continue;
+                };
+                let Ok(entries) = handle.read_dir().try_collect::<Vec<_>>().await else {
+                    continue;
+                };
+                let mut names = entries.iter()
+                    .map(|entry| entry.filename().to_string_lossy().into_owned())
+                    .filter(|entry| entry != "." && entry != "..")
+                    .filter(|entry| !entry.starts_with('.') || name.starts_with('.'))
+                    .filter(|entry| glob.is_match(entry))
+                    .collect::<Vec<_>>();
+                names.sort();
+                matched.extend(names.into_iter().map(|entry| dir.join(entry)));
+            }
+            paths = matched;
+        }
+
+        if paths.is_empty() {
+            paths.push(pattern.to_path_buf());
+        }
+        Ok(paths)
+    }
+
+    /// Entries of a remote directory sorted by name, with their paths relative to it.
+    ///
+    /// Hidden entries, `.` and `..` included, are only listed with `all`.
+    pub async fn list_dir(&self, remote_dir: &Path, all: bool) -> Result<Vec<FileStat>> {
+        let mut fs = self.sftp().await?.fs();
+        let mut stats = Vec::new();
+        for entry in self.read_dir(remote_dir).await? {
+            let name = entry.filename();
+            if !all && name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let mut stat = FileStat::new(name, &entry.metadata());
+            if stat.kind == FileKind::Symlink {
+                stat.target = fs.read_link(remote_dir.join(name)).await.ok();
+            }
+            stats.push(stat);
+        }
+        stats.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(stats)
+    }
+
+    /// Opens a remote file for buffered reading over sftp, no remote `cat` involved.
+    pub async fn open_read(&self, remote_path: &Path) -> Result<impl AsyncBufRead + Unpin + Send + 'static> {
+        let file = self.sftp().await?.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        Ok(Box::pin(TokioCompatFile::new(file)))
+    }
+
+    /// Reads a whole remote file, which has to be valid UTF-8.
+    pub async fn read_to_string(&self, remote_path: &Path) -> Result<String> {
+        let mut content = String::new();
+        self.open_read(remote_path).await?.read_to_string(&mut content).await?;
+        Ok(content)
+    }
+
+    /// Streams the lines of a remote file as they are read, without their line endings.
+    pub fn read_lines<'a>(&'a self, remote_path: &Path) -> impl Stream<Item = Result<String>> + 'a {
+        let remote_path = remote_path.to_path_buf();
+
+        let start = stream::once(async move { self.open_read(&remote_path).await.map(AsyncBufReadExt::lines) });
+
+        // a read error ends the stream, the position in the file is unknown after it
+
+        start.map_ok(|lines| stream::unfold(Some(lines), |lines| async move {
+            let mut lines = lines?;
+            match lines.next_line().await {
+                Ok(Some(line)) => Some((Ok(line), Some(lines))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        })).try_flatten()
+    }
+}
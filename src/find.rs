@@ -0,0 +1,108 @@
+use std::{collections::VecDeque, path::Path, time::SystemTime};
+use futures::{Stream, TryStreamExt, stream};
+use regex::Regex;
+use crate::{client::Client, error::{Error, Result}, fileops::{FileKind, FileStat}, glob::Glob};
+
+/// Which entries [`Client::find`] yields, every set predicate has to hold.
+#[derive(Debug, Default, Clone)]
+pub struct FindFilter {
+    /// Matched against the last path component
+    pub name: Option<Glob>,
+
+    /// Searched in the whole path
+    pub regex: Option<Regex>,
+
+    pub kind: Option<FileKind>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<SystemTime>,
+    pub modified_before: Option<SystemTime>,
+
+    /// Levels below the root to descend into, 0 only looks at the root itself
+    pub max_depth: Option<usize>,
+}
+
+impl FindFilter {
+    pub fn matches(&self, stat: &FileStat) -> bool {
+        let path = stat.path.to_string_lossy();
+        let name = stat.path.file_name().map_or(path.clone(), |name| name.to_string_lossy());
+
+        self.name.as_ref().is_none_or(|glob| glob.is_match(&name))
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(&path))
+            && self.kind.is_none_or(|kind| stat.kind == kind)
+            && self.min_size.is_none_or(|min| stat.len.is_some_and(|len| len >= min))
+            && self.max_size.is_none_or(|max| stat.len.is_some_and(|len| len <= max))
+            && self.modified_after.is_none_or(|after| stat.modified.is_some_and(|modified| modified >= after))
+            && self.modified_before.is_none_or(|before| stat.modified.is_some_and(|modified| modified <= before))
+    }
+}
+
+struct Walk<'a> {
+    client: &'a Client,
+    filter: FindFilter,
+
+    // entries of every directory being walked, with the depth of those entries
+    stack: Vec<(VecDeque<FileStat>, usize)>,
+
+    // results to hand out before walking on
+    ready: VecDeque<Result<FileStat>>,
+}
+
+impl Client {
+    /// Walks a remote tree over sftp in the order find prints it, yielding matching entries as they are read.
+    ///
+    /// Symlinks are reported, not followed. A directory that can't be read yields its error and the
+    /// walk carries on with the rest, like find does.
+    pub fn find<'a>(&'a self, root: &Path, filter: FindFilter) -> impl Stream<Item = Result<FileStat>> + 'a {
+        let root = root.to_path_buf();
+
+        let start = stream::once(async move {
+            let stat = self.lstat(&root).await?;
+            Ok::<_, Error>(Walk { client: self, filter, stack: vec![(VecDeque::from([stat]), 0)], ready: VecDeque::new() })
+        });
+
+        start.map_ok(|walk| stream::unfold(walk, |mut walk| async move {
+            let item = walk.next().await?;
+            Some((item, walk))
+        })).try_flatten()
+    }
+}
+
+impl Walk<'_> {
+    async fn next(&mut self) -> Option<Result<FileStat>> {
+        loop {
+            if let Some(item) = self.ready.pop_front() {
+                return Some(item);
+            }
+            let (entries, depth) = self.stack.last_mut()?;
+            let depth = *depth;
+            let Some(stat) = entries.pop_front() else {
+                self.stack.pop();
+                continue;
+            };
+
+            // a directory is listed before it is handed out, its entries come right after it
+
+            let descend = stat.kind == FileKind::Dir && self.filter.max_depth.is_none_or(|max| depth < max);
+            let listing = if descend { Some(self.list(&stat.path).await) } else { None };
+            if self.filter.matches(&stat) {
+                self.ready.push_back(Ok(stat));
+            }
+            match listing {
+                Some(Ok(entries)) => self.stack.push((entries, depth + 1)),
+                Some(Err(e)) => self.ready.push_back(Err(e)),
+                None => {}
+            }
+        }
+    }
+
+    async fn list(&self, dir: &Path) -> Result<VecDeque<FileStat>> {
+        let mut entries = self.client.read_dir(dir).await?
+            .into_iter()
+            .filter(|entry| entry.filename() != Path::new(".") && entry.filename() != Path::new(".."))
+            .map(|entry| FileStat::new(&dir.join(entry.filename()), &entry.metadata()))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries.into())
+    }
+}
@@ -0,0 +1,186 @@
+use std::{fmt, str::FromStr};
+use regex::Regex;
+use crate::error::{Error, Result};
+
+/// A shell style pattern: `*` and `?` stay within one path component, `**` crosses them,
+/// `[abc]`, `[a-z]` and `[!abc]` match one character.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+
+                    // `**/` also matches no directory at all, so `**/x` finds a top level x
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    let mut class = String::new();
+                    let mut closed = false;
+                    if let Some(&negate) = chars.peek() {
+                        if negate == '!' || negate == '^' {
+                            chars.next();
+                            class.push_str("^/");
+                        }
+                    }
+
+                    // a `]` right after the opening `[` or `[!` is a member, not the end
+                    let mut empty = true;
+                    for c in chars.by_ref() {
+                        match c {
+                            ']' if !empty => {
+                                closed = true;
+                                break;
+                            }
+                            '\\' | '[' | ']' | '&' | '~' | '^' => {
+                                class.push('\\');
+                                class.push(c);
+                            }
+                            _ => class.push(c),
+                        }
+                        empty = false;
+                    }
+                    if !closed {
+                        return Err(Error::parse("glob", pattern));
+                    }
+                    regex.push('[');
+                    regex.push_str(&class);
+                    regex.push(']');
+                }
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        let regex = Regex::new(&regex).map_err(|_| Error::parse("glob", pattern))?;
+        Ok(Self { pattern: pattern.to_string(), regex })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+
+    /// Matches a relative path the way rsync and gitignore do: a pattern without `/` is
+    /// compared against the last component only.
+    pub fn matches_path(&self, path: &str) -> bool {
+        if self.pattern.contains('/') {
+            self.is_match(path)
+        } else {
+            self.is_match(path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+
+    /// Whether the pattern contains any wildcard at all.
+    pub fn is_literal(&self) -> bool {
+        !self.pattern.contains(['*', '?', '['])
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl FromStr for Glob {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        Self::new(pattern)
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str) -> Glob {
+        Glob::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn star_stays_in_one_component() {
+        assert!(glob("*.rs").is_match("main.rs"));
+        assert!(!glob("*.rs").is_match("src/main.rs"));
+        assert!(glob("src/*.rs").is_match("src/main.rs"));
+        assert!(glob("?.txt").is_match("a.txt"));
+        assert!(!glob("?.txt").is_match("/.txt"));
+    }
+
+    #[test]
+    fn double_star_crosses_components() {
+        assert!(glob("**/*.rs").is_match("main.rs"));
+        assert!(glob("**/*.rs").is_match("src/bin/main.rs"));
+        assert!(glob("src/**").is_match("src/bin/main.rs"));
+        assert!(glob("a/**/b").is_match("a/b"));
+        assert!(glob("a/**/b").is_match("a/x/y/b"));
+        assert!(!glob("a/**/b").is_match("a/xb"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob("[abc].txt").is_match("b.txt"));
+        assert!(!glob("[abc].txt").is_match("d.txt"));
+        assert!(glob("[a-c]x").is_match("cx"));
+        assert!(glob("[]a]").is_match("]"));
+        assert!(glob("[]a]").is_match("a"));
+        assert!(glob("x[a^]").is_match("x^"));
+        assert!(glob("[[]").is_match("["));
+    }
+
+    #[test]
+    fn negated_classes() {
+        assert!(glob("[!abc]").is_match("d"));
+        assert!(!glob("[!abc]").is_match("a"));
+        assert!(glob("[^abc]").is_match("d"));
+        assert!(!glob("[!a]").is_match("/"));
+
+        // a leading `]` right after the `!` is a member too
+        assert!(!glob("[!]a]").is_match("]"));
+        assert!(!glob("[!]a]").is_match("a"));
+        assert!(glob("[!]a]").is_match("b"));
+        assert!(!glob("[!]a]").is_match("ba]"));
+    }
+
+    #[test]
+    fn unclosed_class_is_an_error() {
+        assert!(Glob::new("[abc").is_err());
+        assert!(Glob::new("[]").is_err());
+        assert!(Glob::new("[!]").is_err());
+    }
+
+    #[test]
+    fn matches_path_uses_the_name_without_a_slash() {
+        assert!(glob("*.rs").matches_path("src/bin/main.rs"));
+        assert!(glob("target").matches_path("sub/target"));
+        assert!(!glob("src/*.rs").matches_path("lib/src/main.rs"));
+        assert!(glob("src/*.rs").matches_path("src/main.rs"));
+    }
+
+    #[test]
+    fn literal() {
+        assert!(glob("src/main.rs").is_literal());
+        assert!(!glob("*.rs").is_literal());
+        assert!(!glob("[ab]").is_literal());
+    }
+}
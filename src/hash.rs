@@ -0,0 +1,168 @@
+use std::{io, path::Path, pin::Pin, task::{ready, Context, Poll}};
+use sha2::{Digest, Sha256};
+use shell_escape::unix::escape;
+use openssh::Stdio;
+use openssh_sftp_client::file::TokioCompatFile;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use crate::{client::{Client, check_status}, error::{Error, Result}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+
+    // shell snippet hashing its stdin, exiting 127 when no tool is installed
+
+    fn remote_tool(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "if command -v sha256sum >/dev/null 2>&1; then sha256sum; elif command -v shasum >/dev/null 2>&1; then shasum -a 256; else exit 127; fi",
+            Self::Blake3 => "if command -v b3sum >/dev/null 2>&1; then b3sum; else exit 127; fi",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Lowercase hex digest, the way sha256sum and b3sum print it.
+    pub fn finalize(self) -> String {
+        match self {
+            Self::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+
+    /// Feeds up to `len` bytes of `reader`.
+    pub async fn update_from(&mut self, reader: &mut (impl AsyncRead + Unpin), len: u64) -> io::Result<()> {
+        let mut reader = reader.take(len);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.update(&buf[..n]);
+        }
+    }
+}
+
+/// Passes reads through while hashing everything that goes by.
+#[derive(Debug)]
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Hasher>,
+}
+
+impl<R> HashingReader<R> {
+    /// Without a hasher this is a plain pass-through, which keeps optional verification simple.
+    pub fn new(inner: R, hasher: Option<Hasher>) -> Self {
+        Self { inner, hasher }
+    }
+
+    pub fn finish(self) -> Option<String> {
+        self.hasher.map(Hasher::finalize)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf.filled()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub(crate) async fn sha256_prefix(reader: &mut (impl AsyncRead + Unpin), len: u64) -> Result<String> {
+    let mut hasher = Hasher::new(HashAlgorithm::Sha256);
+    hasher.update_from(reader, len).await?;
+    Ok(hasher.finalize())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Client {
+    /// Hex digest of a remote file.
+    ///
+    /// Computed on the remote host when it has the tool for it, otherwise the file is read back over sftp.
+    pub async fn remote_hash(&self, remote_path: &Path, algorithm: HashAlgorithm) -> Result<String> {
+        self.remote_hash_prefix(remote_path, algorithm, None).await
+    }
+
+    /// Fails with [`Error::ChecksumMismatch`] unless the remote file hashes to `local`.
+    pub(crate) async fn verify_remote_hash(&self, remote_path: &Path, algorithm: HashAlgorithm, local: String) -> Result<()> {
+        let remote = self.remote_hash(remote_path, algorithm).await?;
+        if remote != local {
+            return Err(Error::ChecksumMismatch { path: remote_path.to_path_buf(), local, remote });
+        }
+        Ok(())
+    }
+
+    /// Hex sha256 of the first `len` bytes of a remote file.
+    pub async fn remote_sha256_prefix(&self, remote_path: &Path, len: u64) -> Result<String> {
+        self.remote_hash_prefix(remote_path, HashAlgorithm::Sha256, Some(len)).await
+    }
+
+    async fn remote_hash_prefix(&self, remote_path: &Path, algorithm: HashAlgorithm, len: Option<u64>) -> Result<String> {
+        match self.remote_hash_by_command(remote_path, algorithm, len).await {
+            Err(Error::RemoteCommand { .. }) => {}
+            result => return result,
+        }
+
+        // no tool, or the redirect failed; reading back also gives a proper sftp error for the latter
+
+        let remote_file = self.sftp().await?.open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+        let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update_from(&mut remote_file, len.unwrap_or(u64::MAX)).await?;
+        Ok(hasher.finalize())
+    }
+
+    async fn remote_hash_by_command(&self, remote_path: &Path, algorithm: HashAlgorithm, len: Option<u64>) -> Result<String> {
+        let path = escape(remote_path.to_string_lossy());
+        let command = match len {
+            Some(len) => format!("head -c {} < {} | {{ {}; }}", len, path, algorithm.remote_tool()),
+            None => format!("{{ {}; }} < {}", algorithm.remote_tool(), path),
+        };
+        let output = self.session().raw_command(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .await?;
+        check_status(&command, Ok(output.status))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.split_whitespace().next() {
+            Some(digest) if digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(digest.to_ascii_lowercase()),
+            _ => Err(Error::parse("hash tool output", stdout)),
+        }
+    }
+}
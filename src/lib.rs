@@ -0,0 +1,32 @@
+mod archive;
+mod chunked;
+mod client;
+mod delta;
+pub mod error;
+mod exec;
+mod fileops;
+mod find;
+mod glob;
+mod hash;
+mod limit;
+mod process;
+mod progress;
+mod sync;
+pub mod sftp;
+mod transfer;
+
+pub use archive::Compression;
+pub use chunked::{ChunkOptions, DEFAULT_CHUNK_SIZE, DEFAULT_PARALLELISM};
+pub use client::{Client, ConnectOptions, GetOptions, PutOptions, Resume, wait_for_ssh_connectable};
+pub use delta::{DeltaOptions, DeltaReport};
+pub use error::{Error, Result, RemoteStatus};
+pub use exec::{ExecOptions, ExecOutput};
+pub use fileops::{FileKind, FileStat};
+pub use find::FindFilter;
+pub use glob::Glob;
+pub use hash::{HashAlgorithm, Hasher, HashingReader};
+pub use limit::{LimitedReader, RateLimiter, parse_rate, parse_size};
+pub use progress::{Progress, ProgressCallback, ProgressStream};
+pub use process::{ProcessInfo, ProcessFilter, ProcessNode, SortKey, sort_processes, build_tree, select_subtrees, sort_tree};
+pub use sync::{SyncOptions, SyncReport};
+pub use transfer::{DEFAULT_CONCURRENCY, TransferReport};
@@ -0,0 +1,148 @@
+use std::{future::Future, io, pin::Pin, sync::{Arc, Mutex}, task::{ready, Context, Poll}, time::Duration};
+use tokio::{io::{AsyncRead, ReadBuf}, time::{sleep, Instant, Sleep}};
+use crate::error::{Error, Result};
+
+/// A token bucket shared by every transfer it is cloned into.
+///
+/// The bucket holds at most one second worth of bytes, so an idle limiter allows a short burst.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Self { bucket: Arc::new(Mutex::new(Bucket { rate, tokens: rate, last: Instant::now() })) }
+    }
+
+    // takes n tokens, possibly going into debt, and tells how long to wait until the debt is paid
+
+    fn consume(&self, n: usize) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        bucket.tokens = (bucket.tokens + (now - bucket.last).as_secs_f64() * bucket.rate).min(bucket.rate);
+        bucket.last = now;
+        bucket.tokens -= n as f64;
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / bucket.rate))
+    }
+}
+
+/// Parses a rate like `5MiB/s`, `500k` or `1000000`, see [`parse_size`] for the units.
+pub fn parse_rate(input: &str) -> Result<u64> {
+    let value = input.trim();
+    parse_size(value.strip_suffix("/s").unwrap_or(value)).map_err(|_| Error::parse("rate", input))
+}
+
+/// Parses a byte count like `8MiB`, `500k` or `1000000`.
+///
+/// `K`, `M` and `G` alone are binary units like their `KiB` forms, `KB`, `MB` and `GB` are decimal.
+pub fn parse_size(input: &str) -> Result<u64> {
+    let value = input.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        _ => return Err(Error::parse("size", input)),
+    };
+
+    // fractions are fine as long as at least one whole byte is left
+    match number.parse::<f64>().map(|number| (number * multiplier as f64) as u64) {
+        Ok(bytes) if bytes > 0 => Ok(bytes),
+        _ => Err(Error::parse("size", input)),
+    }
+}
+
+/// Holds reads back to the rate of a [`RateLimiter`].
+///
+/// Each read is allowed through and paid for afterwards, by sleeping before the next one.
+#[derive(Debug)]
+pub struct LimitedReader<R> {
+    inner: R,
+    limiter: Option<RateLimiter>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> LimitedReader<R> {
+    /// Without a limiter this is a plain pass-through.
+    pub fn new(inner: R, limiter: Option<RateLimiter>) -> Self {
+        Self { inner, limiter, delay: None }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if let Some(delay) = &mut self.delay {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - filled;
+
+        if let Some(wait) = self.limiter.as_ref().and_then(|limiter| limiter.consume(n)) {
+            self.delay = Some(Box::pin(sleep(wait)));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1000000").unwrap(), 1_000_000);
+        assert_eq!(parse_size("512B").unwrap(), 512);
+        assert_eq!(parse_size("8MiB").unwrap(), 8 << 20);
+        assert_eq!(parse_size(" 1.5 G ").unwrap(), 3 << 29);
+        assert_eq!(parse_size("0.5k").unwrap(), 512);
+    }
+
+    #[test]
+    fn single_letters_are_binary_two_letters_decimal() {
+        for (binary, decimal, bytes) in [("k", "kb", 1000), ("M", "MB", 1_000_000), ("g", "GB", 1_000_000_000)] {
+            assert_eq!(parse_size(&format!("2{binary}")).unwrap(), parse_size(&format!("2{binary}iB")).unwrap());
+            assert_eq!(parse_size(&format!("2{decimal}")).unwrap(), 2 * bytes);
+        }
+        assert_eq!(parse_size("1K").unwrap(), 1024);
+        assert_eq!(parse_size("1KB").unwrap(), 1000);
+    }
+
+    #[test]
+    fn zero_and_garbage_are_rejected() {
+        for input in ["0", "0k", "0.0001", "", "k", "12x", "-5M", "1.2.3M", "5 M B"] {
+            assert!(parse_size(input).is_err(), "{input:?} parsed");
+        }
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("5MiB/s").unwrap(), 5 << 20);
+        assert_eq!(parse_rate("500k").unwrap(), 500 << 10);
+        assert_eq!(parse_rate("2MB/s").unwrap(), 2_000_000);
+        assert!(parse_rate("0/s").is_err());
+        assert!(parse_rate("5M/min").is_err());
+        assert!(matches!(parse_rate("fast"), Err(Error::Parse { what: "rate", .. })));
+    }
+}
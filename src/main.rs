@@ -1,12 +1,34 @@
-use std::{error::Error, time::Duration, path::{Path, PathBuf}};
-use shell_escape::unix::escape;
-use openssh::{Session, SessionBuilder, Stdio, KnownHosts};
-use openssh_sftp_client::{Sftp, file::TokioCompatFile};
-use clap::Parser;
-use tokio::{io::{copy, AsyncRead, BufReader, AsyncBufReadExt}, time::{timeout, interval}, net::TcpStream};
+use std::{error::Error as _, path::{Path, PathBuf}, pin::pin, process::ExitCode, time::{Duration, SystemTime, UNIX_EPOCH}};
+use futures::StreamExt;
+use clap::{CommandFactory, Parser, Subcommand, error::ErrorKind};
+use openssh::{ForwardType, Socket};
+use tokio::io::{stdin, stdout, AsyncWriteExt};
 use regex::Regex;
+use learning_openssh::{ChunkOptions, Client, Compression, ConnectOptions, DEFAULT_CONCURRENCY, DeltaOptions, Error, ExecOptions, FileKind, FindFilter, GetOptions, Glob, HashAlgorithm, ProcessFilter, PutOptions, RateLimiter, Resume, Result, SortKey, parse_rate, parse_size, sort_processes, build_tree, select_subtrees, sort_tree, SyncOptions, wait_for_ssh_connectable};
+use output::{Format, print_json, print_listing, print_processes, print_process_tree, print_stat, progress_callback};
+
+mod output;
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0   success
+  *   exec passes through the remote exit code (128 + N when killed by signal N), its own
+      failures exit 255 like ssh and name one of the codes below in the error message
+  10  connect timeout
+  11  authentication failure
+  12  host key mismatch
+  13  other ssh failure
+  14  remote command exited non-zero
+  15  sftp failure
+  16  parse failure
+  17  local i/o failure
+  18  processes still running after waiting
+  19  checksum mismatch after transfer
+  20  remote file not found
+  21  remote permission denied";
 
 #[derive(Debug, Parser)]
+#[clap(after_help = EXIT_CODES)]
 struct Args {
     #[clap(long)]
     user: String,
@@ -19,114 +41,833 @@ struct Args {
 
     #[clap(long, default_value = "~/.ssh/id_rsa")]
     keyfile: PathBuf,
+
+    /// Give up waiting for the ssh port after this many seconds (waits forever by default)
+    #[clap(long)]
+    wait_timeout: Option<u64>,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Wait until the ssh port accepts connections
+    Wait,
+
+    /// Run a command on the remote host, exiting with its exit code
+    Exec {
+        /// Feed this file to the remote stdin
+        #[clap(long)]
+        stdin_file: Option<PathBuf>,
+
+        #[clap(last = true, required = true)]
+        argv: Vec<String>,
+    },
+
+    /// List remote processes
+    Ps {
+        /// Only processes owned by this user (repeatable)
+        #[clap(long = "user")]
+        users: Vec<String>,
+
+        /// Only this pid (repeatable)
+        #[clap(long = "pid")]
+        pids: Vec<u32>,
+
+        /// Only processes whose command line matches this regex
+        #[clap(long = "match")]
+        pattern: Option<Regex>,
+
+        /// Heaviest first
+        #[clap(long)]
+        sort: Option<Sort>,
+
+        /// Show only the first N processes (the first N trees with --tree)
+        #[clap(long)]
+        top: Option<usize>,
+
+        /// Show parent/child relationships; filters select whole subtrees
+        #[clap(long)]
+        tree: bool,
+
+        #[clap(long, value_enum, default_value = "table")]
+        format: Format,
+    },
+
+    /// Signal remote processes selected by pid, user or command regex
+    #[clap(group(clap::ArgGroup::new("selector").required(true).multiple(true).args(["users", "pids", "pattern"])))]
+    Kill {
+        /// Signal name or number
+        #[clap(short, long, default_value = "TERM")]
+        signal: String,
+
+        /// Processes owned by this user (repeatable)
+        #[clap(long = "user")]
+        users: Vec<String>,
+
+        /// This pid (repeatable)
+        #[clap(long = "pid")]
+        pids: Vec<u32>,
+
+        /// Processes whose command line matches this regex
+        #[clap(long = "match")]
+        pattern: Option<Regex>,
+
+        /// Only print the processes that would be signalled
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Wait until the signalled processes have exited
+        #[clap(long)]
+        wait: bool,
+
+        /// Give up waiting after this many seconds
+        #[clap(long, requires = "wait")]
+        timeout: Option<u64>,
+    },
+
+    /// Upload a local file ("-" reads stdin)
+    Put {
+        /// Upload a directory tree, creating remote directories as needed
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// Files transferred at once with -r
+        #[clap(short, long, default_value_t = DEFAULT_CONCURRENCY)]
+        jobs: usize,
+
+        /// Write to a temporary file and rename it into place when complete
+        #[clap(long, conflicts_with = "resume")]
+        atomic: bool,
+
+        /// Continue a partial remote file instead of starting over
+        #[clap(long)]
+        resume: bool,
+
+        /// With --resume, compare a sha256 of the partial file before continuing
+        #[clap(long, requires = "resume")]
+        verify_prefix: bool,
+
+        /// Hash the upload and compare it with the remote file afterwards
+        #[clap(long, value_enum)]
+        verify: Option<Verify>,
+
+        /// Report progress on stderr, as a bar on a terminal and JSON lines otherwise
+        #[clap(long)]
+        progress: bool,
+
+        /// Bandwidth cap shared by all files, e.g. 5MiB/s or 500k
+        #[clap(long, value_parser = parse_rate)]
+        limit: Option<u64>,
+
+        /// Split a single file into ranges transferred over this many sftp handles at once
+        #[clap(long, conflicts_with_all = ["recursive", "atomic", "resume", "progress"])]
+        parallel: Option<usize>,
+
+        /// Range size with --parallel
+        #[clap(long, requires = "parallel", value_parser = parse_size, default_value = "8MiB")]
+        chunk_size: u64,
+
+        /// Send only the blocks that differ from the existing remote file
+        #[clap(long, conflicts_with_all = ["recursive", "atomic", "resume", "progress", "parallel"])]
+        delta: bool,
+
+        /// With -r, send the tree as one tar stream instead of file by file over sftp
+        #[clap(long, requires = "recursive", conflicts_with_all = ["atomic", "resume", "verify", "progress", "limit"])]
+        tar: bool,
+
+        /// Compress the tar stream
+        #[clap(long, value_enum, requires = "tar")]
+        compress: Option<Compress>,
+
+        local: PathBuf,
+        remote: PathBuf,
+    },
+
+    /// Download a remote file ("-" writes stdout)
+    Get {
+        /// Copy mode and mtime of the remote file
+        #[clap(short, long)]
+        preserve: bool,
+
+        /// Download a directory tree, skipping special files
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// Files transferred at once with -r
+        #[clap(short, long, default_value_t = DEFAULT_CONCURRENCY)]
+        jobs: usize,
+
+        /// Continue a partial local file instead of starting over
+        #[clap(long)]
+        resume: bool,
+
+        /// With --resume, compare a sha256 of the partial file before continuing
+        #[clap(long, requires = "resume")]
+        verify_prefix: bool,
+
+        /// Hash the download and compare it with the remote file afterwards
+        #[clap(long, value_enum)]
+        verify: Option<Verify>,
+
+        /// Report progress on stderr, as a bar on a terminal and JSON lines otherwise
+        #[clap(long)]
+        progress: bool,
+
+        /// Bandwidth cap shared by all files, e.g. 5MiB/s or 500k
+        #[clap(long, value_parser = parse_rate)]
+        limit: Option<u64>,
+
+        /// Split a single file into ranges transferred over this many sftp handles at once
+        #[clap(long, conflicts_with_all = ["recursive", "resume", "progress"])]
+        parallel: Option<usize>,
+
+        /// Range size with --parallel
+        #[clap(long, requires = "parallel", value_parser = parse_size, default_value = "8MiB")]
+        chunk_size: u64,
+
+        /// With -r, receive the tree as one tar stream instead of file by file over sftp
+        #[clap(long, requires = "recursive", conflicts_with_all = ["resume", "verify", "progress", "limit"])]
+        tar: bool,
+
+        /// Compress the tar stream
+        #[clap(long, value_enum, requires = "tar")]
+        compress: Option<Compress>,
+
+        remote: PathBuf,
+        local: PathBuf,
+    },
+
+    /// Upload what differs between a local tree and a remote one
+    Sync {
+        /// Files transferred at once
+        #[clap(short, long, default_value_t = DEFAULT_CONCURRENCY)]
+        jobs: usize,
+
+        /// Compare content hashes of equally sized files instead of their mtimes
+        #[clap(long, value_enum)]
+        checksum: Option<Verify>,
+
+        /// Remove remote files that don't exist locally
+        #[clap(long)]
+        delete: bool,
+
+        /// Only sync files matching this glob, may be repeated
+        #[clap(long)]
+        include: Vec<Glob>,
+
+        /// Skip entries matching this glob, may be repeated
+        #[clap(long)]
+        exclude: Vec<Glob>,
+
+        /// Only print what would be uploaded and deleted
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Write each file to a temporary file and rename it into place when complete
+        #[clap(long)]
+        atomic: bool,
+
+        /// Bandwidth cap shared by all files, e.g. 5MiB/s or 500k
+        #[clap(long, value_parser = parse_rate)]
+        limit: Option<u64>,
+
+        local: PathBuf,
+        remote: PathBuf,
+    },
+
+    /// List remote directories and files, expanding shell style globs
+    Ls {
+        /// Show mode, owner, size and mtime
+        #[clap(short, long)]
+        long: bool,
+
+        /// Include entries starting with a dot
+        #[clap(short, long)]
+        all: bool,
+
+        /// Print one JSON array of every entry instead
+        #[clap(long)]
+        json: bool,
+
+        #[clap(default_value = ".")]
+        remote: Vec<PathBuf>,
+    },
+
+    /// Remove a remote file, symlink or empty directory
+    Rm {
+        /// Remove directories and everything in them
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// Ignore paths that don't exist
+        #[clap(short, long)]
+        force: bool,
+
+        #[clap(required = true)]
+        remote: Vec<PathBuf>,
+    },
+
+    /// Search a remote tree over sftp, find is not needed on the remote host
+    Find {
+        #[clap(default_value = ".")]
+        root: PathBuf,
+
+        /// Glob matched against the name of each entry
+        #[clap(long)]
+        name: Option<Glob>,
+
+        /// Regex searched in the whole path of each entry
+        #[clap(long)]
+        regex: Option<Regex>,
+
+        #[clap(long = "type", value_enum)]
+        kind: Option<FindType>,
+
+        /// At least this many bytes, e.g. 10M
+        #[clap(long, value_parser = parse_size)]
+        min_size: Option<u64>,
+
+        /// At most this many bytes
+        #[clap(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        /// Modified less than this long ago, e.g. 30m, 2h or 7d
+        #[clap(long, value_parser = parse_age)]
+        newer_than: Option<Duration>,
+
+        /// Modified more than this long ago
+        #[clap(long, value_parser = parse_age)]
+        older_than: Option<Duration>,
+
+        /// Levels below the root to descend into
+        #[clap(long)]
+        max_depth: Option<usize>,
+
+        /// Print one JSON object per line
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Show metadata of a remote path
+    Stat {
+        /// Follow a symlink instead of describing it
+        #[clap(short = 'L', long)]
+        dereference: bool,
+
+        #[clap(long)]
+        json: bool,
+
+        remote: PathBuf,
+    },
+
+    /// Print remote files to stdout, read over sftp
+    Cat {
+        #[clap(required = true)]
+        remote: Vec<PathBuf>,
+    },
+
+    /// Change the permission bits of a remote path
+    Chmod {
+        /// Octal, e.g. 644 or 0755
+        #[clap(value_parser = parse_mode)]
+        mode: u32,
+
+        remote: PathBuf,
+    },
+
+    /// Change owner and group of a remote path by number
+    Chown {
+        /// UID, UID:GID or :GID
+        #[clap(value_parser = parse_owner)]
+        owner: (Option<u32>, Option<u32>),
+
+        remote: PathBuf,
+    },
+
+    /// Rename a remote path, replacing the destination
+    Mv {
+        from: PathBuf,
+        to: PathBuf,
+    },
+
+    /// Create a remote symlink at LINK pointing to TARGET
+    Ln {
+        target: PathBuf,
+        link: PathBuf,
+    },
+
+    /// Create a remote directory
+    Mkdir {
+        /// Create missing parents too and don't fail when it exists
+        #[clap(short, long)]
+        parents: bool,
+
+        /// Octal permission bits of the new directory
+        #[clap(short, long, value_parser = parse_mode)]
+        mode: Option<u32>,
+
+        remote: PathBuf,
+    },
+
+    /// Start an interactive shell (no pty)
+    Shell,
+
+    /// Forward a port until interrupted: [HOST:]PORT or a unix socket path on each side
+    Forward {
+        /// Listen on the remote side and connect from the local side
+        #[clap(short = 'R', long)]
+        remote: bool,
+
+        listen: String,
+
+        connect: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Sort {
+    Cpu,
+    Mem,
+}
+
+impl From<Sort> for SortKey {
+    fn from(sort: Sort) -> Self {
+        match sort {
+            Sort::Cpu => Self::Cpu,
+            Sort::Mem => Self::Mem,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Verify {
+    Sha256,
+    Blake3,
+}
+
+impl From<Verify> for HashAlgorithm {
+    fn from(verify: Verify) -> Self {
+        match verify {
+            Verify::Sha256 => Self::Sha256,
+            Verify::Blake3 => Self::Blake3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum FindType {
+    #[value(name = "f")]
+    File,
+    #[value(name = "d")]
+    Dir,
+    #[value(name = "l")]
+    Symlink,
+    #[value(name = "p")]
+    Fifo,
+    #[value(name = "s")]
+    Socket,
+    #[value(name = "b")]
+    BlockDevice,
+    #[value(name = "c")]
+    CharDevice,
+}
+
+impl From<FindType> for FileKind {
+    fn from(kind: FindType) -> Self {
+        match kind {
+            FindType::File => Self::File,
+            FindType::Dir => Self::Dir,
+            FindType::Symlink => Self::Symlink,
+            FindType::Fifo => Self::Fifo,
+            FindType::Socket => Self::Socket,
+            FindType::BlockDevice => Self::BlockDevice,
+            FindType::CharDevice => Self::CharDevice,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Compress {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compress> for Compression {
+    fn from(compress: Compress) -> Self {
+        match compress {
+            Compress::Gzip => Self::Gzip,
+            Compress::Zstd => Self::Zstd,
+        }
+    }
+}
+
+impl From<&Args> for ConnectOptions {
+    fn from(args: &Args) -> Self {
+        Self {
+            user: args.user.clone(),
+            host: args.host.clone(),
+            port: args.port,
+            keyfile: args.keyfile.clone(),
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     let args = Args::parse();
-    let host = &args.host;
-    let port = args.port;
+    check_stdio(&args);
 
-    wait_for_ssh_connectable(host, port).await?;
+    // every code may come from the remote command under exec, so its own failures take ssh's 255
+    let exec = matches!(args.command, Command::Exec { .. });
 
-    let session = SessionBuilder::default()
-        .user(args.user)
-        .port(args.port)
-        .keyfile(args.keyfile)
-        .connect_timeout(Duration::from_secs(10))
-        .known_hosts_check(KnownHosts::Add)
-        .server_alive_interval(Duration::from_secs(60))
-        .connect_mux(args.host)
-        .await?;
+    match run(args).await {
+        Ok(code) => code,
+        Err(e) if exec => {
+            print_error(&format!("error {}", e.exit_code()), &e);
+            ExitCode::from(255)
+        }
+        Err(e) => {
+            print_error("error", &e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    command_list(&session).await?;
+async fn run(args: Args) -> Result<ExitCode> {
+    wait_for_ssh_connectable(&args.host, args.port, args.wait_timeout.map(Duration::from_secs)).await?;
 
-    put_data_file(&session, Path::new("test.txt"), &b"hey"[..]).await?;
+    if let Command::Wait = args.command {
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    Ok(())
-}
+    let client = Client::connect((&args).into()).await?;
 
-async fn wait_for_ssh_connectable(host: &str, port: u16) -> Result<(), Box<dyn Error>> {
-    
-    // lightweight ssh connection check than connect
+    let mut code = ExitCode::SUCCESS;
 
-    let mut interval = interval(Duration::from_secs(10));
+    match args.command {
+        Command::Wait => unreachable!("handled before connecting"),
+        Command::Exec { stdin_file, argv } => {
+            let output = client.exec(&argv, &ExecOptions { stdin_file, ..Default::default() }).await?;
+            code = ExitCode::from(output.status.exit_code());
+        }
+        Command::Ps { users, pids, pattern, sort, top, tree: true, format } => {
+            let filter = ProcessFilter { users, pids, pattern };
+            let mut roots = build_tree(client.processes().await?);
+            if !filter.is_empty() {
+                roots = select_subtrees(roots, &filter);
+            }
+            if let Some(sort) = sort {
+                sort_tree(&mut roots, sort.into());
+            }
+            if let Some(top) = top {
+                roots.truncate(top);
+            }
+            print_process_tree(&roots, format)?;
+        }
+        Command::Ps { users, pids, pattern, sort, top, tree: false, format } => {
+            let filter = ProcessFilter { users, pids, pattern };
+            let mut processes = client.processes().await?.into_iter().filter(|p| filter.matches(p)).collect::<Vec<_>>();
+            if let Some(sort) = sort {
+                sort_processes(&mut processes, sort.into());
+            }
+            if let Some(top) = top {
+                processes.truncate(top);
+            }
+            print_processes(&processes, format)?;
+        }
+        Command::Kill { signal, users, pids, pattern, dry_run, wait, timeout } => {
+            let filter = ProcessFilter { users, pids, pattern };
+            let processes = if dry_run {
+                client.processes().await?.into_iter().filter(|p| filter.matches(p)).collect()
+            } else {
+                client.signal_processes(&filter, &signal).await?
+            };
+            for p in &processes {
+                println!("{}{} {} {}", if dry_run { "would signal " } else { "" }, p.pid, p.user, p.command);
+            }
+            if wait && !dry_run {
+                let pids = processes.iter().map(|p| p.pid).collect::<Vec<_>>();
+                client.wait_for_exit(&pids, Duration::from_secs(1), timeout.map(Duration::from_secs)).await?;
+            }
+        }
+        Command::Put { recursive, jobs, atomic, resume, verify_prefix, verify, progress, limit, parallel, chunk_size, delta, tar, compress, local, remote } => {
+            let options = PutOptions {
+                atomic,
+                resume: resume_mode(resume, verify_prefix),
+                verify: verify.map(Into::into),
+                progress: progress.then(progress_callback),
+                limit: limit.map(RateLimiter::new),
+            };
+            if tar {
+                client.put_dir_tar(&local, &remote, compress.map_or(Compression::None, Into::into)).await?;
+            } else if recursive {
+                client.put_dir(&local, &remote, jobs, &options).await?;
+            } else if is_stdio(&local) {
+                client.put_data_file_with(&remote, stdin(), &options).await?;
+            } else if delta {
+                let report = client.put_file_delta(&local, &remote, &options, &DeltaOptions::default()).await?;
+                eprintln!("sent {} bytes, reused {} bytes", report.sent, report.matched);
+            } else if let Some(parallelism) = parallel {
+                client.put_file_chunked(&local, &remote, &options, &ChunkOptions { chunk_size, parallelism }).await?;
+            } else {
+                client.put_file(&local, &remote, &options).await?;
+            }
+        }
+        Command::Get { preserve, recursive, jobs, resume, verify_prefix, verify, progress, limit, parallel, chunk_size, tar, compress, remote, local } => {
+            let options = GetOptions {
+                preserve,
+                resume: resume_mode(resume, verify_prefix),
+                verify: verify.map(Into::into),
+                progress: progress.then(progress_callback),
+                limit: limit.map(RateLimiter::new),
+            };
+            if tar {
+                client.get_dir_tar(&remote, &local, compress.map_or(Compression::None, Into::into)).await?;
+            } else if recursive {
+                let report = client.get_dir(&remote, &local, jobs, &options).await?;
+                code = report_failures(&report.failed);
+            } else if is_stdio(&local) {
+                let mut out = stdout();
+                client.get_data_file_with(&remote, &mut out, &options).await?;
+                out.flush().await?;
+            } else if let Some(parallelism) = parallel {
+                client.get_file_chunked(&remote, &local, &options, &ChunkOptions { chunk_size, parallelism }).await?;
+            } else {
+                client.get_file(&remote, &local, &options).await?;
+            }
+        }
+        Command::Sync { jobs, checksum, delete, include, exclude, dry_run, atomic, limit, local, remote } => {
+            let options = SyncOptions {
+                checksum: checksum.map(Into::into),
+                delete,
+                include,
+                exclude,
+                dry_run,
+                put: PutOptions { atomic, limit: limit.map(RateLimiter::new), ..PutOptions::default() },
+            };
+            let report = client.sync_dir(&local, &remote, jobs, &options).await?;
+            let prefix = if dry_run { "would " } else { "" };
+            for path in &report.uploaded {
+                println!("{}upload {}", prefix, path.display());
+            }
+            for path in &report.deleted {
+                println!("{}delete {}", prefix, path.display());
+            }
+            code = report_failures(&report.failed);
+        }
+        Command::Ls { long, all, json, remote } => {
+            let mut paths = Vec::new();
+            for pattern in &remote {
+                paths.extend(client.expand_glob(pattern).await?);
+            }
+
+            // like ls: plain files first, then every directory with its contents
 
-    loop {
-        match timeout(Duration::from_secs(5), TcpStream::connect((host, port))).await {
-            Err(_) => {
-                eprintln!("waiting for ssh: timeout");
-                interval.tick().await;
+            let mut files = Vec::new();
+            let mut dirs = Vec::new();
+            for path in paths {
+                let stat = client.lstat(&path).await?;
+                let is_dir = stat.kind == FileKind::Dir || (stat.kind == FileKind::Symlink && !long && client.stat(&path).await.is_ok_and(|target| target.kind == FileKind::Dir));
+                match is_dir {
+                    true => dirs.push(path),
+                    false => files.push(stat),
+                }
             }
-            Ok(Err(e)) => {
-                eprintln!("waiting for ssh: {}", e);
-                interval.tick().await;
+
+            if json {
+                for dir in &dirs {
+                    files.extend(client.list_dir(dir, all).await?.into_iter().map(|mut stat| {
+                        stat.path = dir.join(&stat.path);
+                        stat
+                    }));
+                }
+                print_json(&mut std::io::stdout().lock(), &files)?;
+            } else {
+                print_listing(&files, long);
+                let headers = files.len() + dirs.len() > 1;
+                for (i, dir) in dirs.iter().enumerate() {
+                    if headers {
+                        if i > 0 || !files.is_empty() {
+                            println!();
+                        }
+                        println!("{}:", dir.display());
+                    }
+                    print_listing(&client.list_dir(dir, all).await?, long);
+                }
             }
-            Ok(Ok(_)) => {
-                return Ok(());
+        }
+        Command::Rm { recursive, force, remote } => {
+            for remote in remote {
+                match client.remove(&remote, recursive).await {
+                    Err(Error::NotFound { .. }) if force => {}
+                    result => result?,
+                }
             }
         }
+        Command::Find { root, name, regex, kind, min_size, max_size, newer_than, older_than, max_depth, json } => {
+            let now = SystemTime::now();
+            let filter = FindFilter {
+                name,
+                regex,
+                kind: kind.map(Into::into),
+                min_size,
+                max_size,
+                modified_after: newer_than.map(|age| now.checked_sub(age).unwrap_or(UNIX_EPOCH)),
+                modified_before: older_than.map(|age| now.checked_sub(age).unwrap_or(UNIX_EPOCH)),
+                max_depth,
+            };
+
+            // unreadable directories are reported and skipped, the walk goes on
+
+            let mut found = pin!(client.find(&root, filter));
+            while let Some(result) = found.next().await {
+                match result {
+                    Ok(stat) if json => println!("{}", serde_json::to_string(&stat).map_err(std::io::Error::from)?),
+                    Ok(stat) => println!("{}", stat.path.display()),
+                    Err(e) => {
+                        print_error("find", &e);
+                        if code == ExitCode::SUCCESS {
+                            code = ExitCode::from(e.exit_code());
+                        }
+                    }
+                }
+            }
+        }
+        Command::Stat { dereference, json, remote } => {
+            let stat = if dereference { client.stat(&remote).await? } else { client.lstat(&remote).await? };
+            if json {
+                print_json(&mut std::io::stdout().lock(), &stat)?;
+            } else {
+                print_stat(&stat);
+            }
+        }
+        Command::Cat { remote } => {
+            let mut out = stdout();
+            for remote in remote {
+                tokio::io::copy_buf(&mut client.open_read(&remote).await?, &mut out).await?;
+            }
+            out.flush().await?;
+        }
+        Command::Chmod { mode, remote } => {
+            client.chmod(&remote, mode).await?;
+        }
+        Command::Chown { owner: (uid, gid), remote } => {
+            client.chown(&remote, uid, gid).await?;
+        }
+        Command::Mv { from, to } => {
+            client.rename(&from, &to).await?;
+        }
+        Command::Ln { target, link } => {
+            client.symlink(&target, &link).await?;
+        }
+        Command::Mkdir { parents, mode, remote } => {
+            client.mkdir(&remote, parents, mode).await?;
+        }
+        Command::Shell => {
+            client.shell().await?;
+        }
+        Command::Forward { remote, listen, connect } => {
+            let forward_type = if remote { ForwardType::Remote } else { ForwardType::Local };
+            client.forward(forward_type, parse_socket(&listen), parse_socket(&connect)).await?;
+            eprintln!("forwarding {} -> {}, press ctrl-c to stop", listen, connect);
+            tokio::signal::ctrl_c().await?;
+        }
     }
-}
 
-async fn command_list(session: &Session) -> Result<(), Box<dyn Error>> {
+    client.close().await?;
 
-    // example for showing executing command and parsing output
+    Ok(code)
+}
 
-    let mut ps_process = session.raw_command(vec!["ps", "auwx"].into_iter().map(|s| escape(s.into()).to_string()).collect::<Vec<_>>().join(" "))
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .await?;
+fn print_error(prefix: &str, e: &Error) {
+    eprint!("{}: {}", prefix, e);
+    let mut source = e.source();
+    while let Some(e) = source {
+        eprint!(": {}", e);
+        source = e.source();
+    }
+    eprintln!();
+}
 
-    let stdout = ps_process.stdout().take().expect("should be piped one");
-    let mut line_stream = BufReader::new(stdout).lines();
+fn report_failures(failed: &[(PathBuf, Error)]) -> ExitCode {
+    for (path, e) in failed {
+        print_error(&path.display().to_string(), e);
+    }
+    match failed.first() {
+        Some((_, e)) => ExitCode::from(e.exit_code()),
+        None => ExitCode::SUCCESS,
+    }
+}
 
-    let first_line = line_stream.next_line().await?;
-    let Some(first_line) = first_line else {
-        return Err("output no line".into());
+fn parse_age(input: &str) -> Result<Duration> {
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(Error::Parse { what: "age", input: input.to_string() }),
     };
-    let headers = first_line.split_whitespace().collect::<Vec<_>>();
-    assert_eq!(headers.len(), 11);
-    assert_eq!(headers.last().expect("last"), &"COMMAND");
-    println!("{:?}", headers);
-
-    let regex = Regex::new(r"^(?:[^\s]+\s+){10}(.*)$").expect("hardcoded regex");
-    while let Some(record) = line_stream.next_line().await? {
-        let captures = regex.captures(&record).expect("regex should match");
-        let command = captures.get(1).expect("should have capture").as_str();
-        println!("{}", command);
+    match number.parse::<u64>().ok().and_then(|number| number.checked_mul(unit)) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(Error::Parse { what: "age", input: input.to_string() }),
     }
+}
 
-    ps_process.wait().await?;
+fn parse_mode(input: &str) -> Result<u32> {
+    match u32::from_str_radix(input, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(Error::Parse { what: "mode", input: input.to_string() }),
+    }
+}
 
-    Ok(())
+fn parse_owner(input: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let id = |id: &str| if id.is_empty() { Ok(None) } else { id.parse().map(Some).map_err(|_| Error::Parse { what: "owner", input: input.to_string() }) };
+    match input.split_once(':') {
+        Some((uid, gid)) if !(uid.is_empty() && gid.is_empty()) => Ok((id(uid)?, id(gid)?)),
+        None if !input.is_empty() => Ok((id(input)?, None)),
+        _ => Err(Error::Parse { what: "owner", input: input.to_string() }),
+    }
 }
 
-async fn put_data_file(session: &Session, remote_path: &Path, mut data: impl AsyncRead + Unpin) -> Result<(), Box<dyn Error>> {
+fn resume_mode(resume: bool, verify_prefix: bool) -> Resume {
+    match (resume, verify_prefix) {
+        (false, _) => Resume::No,
+        (true, false) => Resume::Size,
+        (true, true) => Resume::Hash,
+    }
+}
 
-    // example for putting data to remote file
-    // AsyncRead accepts almost types of input stream, or fixed data
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-    let mut sftp_process = session
-        .subsystem("sftp")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .await?;
+// clap can't tell `-` from a path, flags that need a real local file are rejected here
 
-    let sftp = Sftp::new(
-        sftp_process.stdin().take().expect("should be piped"),
-        sftp_process.stdout().take().expect("should be piped"),
-        Default::default(),
-    ).await?;
+fn check_stdio(args: &Args) {
+    let flag = match &args.command {
+        Command::Put { local, delta: true, .. } if is_stdio(local) => "--delta",
+        Command::Put { local, parallel: Some(_), .. } if is_stdio(local) => "--parallel",
+        Command::Put { local, resume: true, .. } if is_stdio(local) => "--resume",
+        Command::Get { local, parallel: Some(_), .. } if is_stdio(local) => "--parallel",
+        Command::Get { local, resume: true, .. } if is_stdio(local) => "--resume",
+        Command::Get { local, preserve: true, .. } if is_stdio(local) => "--preserve",
+        _ => return,
+    };
+    Args::command().error(ErrorKind::ArgumentConflict, format!("{} needs a local file, not `-`", flag)).exit();
+}
 
-    let remote_file = sftp.create(remote_path).await?;
-    let mut remote_file = Box::pin(TokioCompatFile::new(remote_file)); // tokio copy requires Unpin
+fn parse_socket(spec: &str) -> Socket<'_> {
 
-    copy(&mut data, &mut remote_file).await?;
+    // [HOST:]PORT is a tcp socket, anything else is a unix socket path
 
-    Ok(())
+    let (host, port) = spec.rsplit_once(':').unwrap_or(("localhost", spec));
+    match port.parse() {
+        Ok(port) => Socket::new(host, port),
+        Err(_) => Path::new(spec).into(),
+    }
 }
-
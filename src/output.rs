@@ -0,0 +1,283 @@
+use std::{io::{self, IsTerminal, Write}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::json;
+use learning_openssh::{FileKind, FileStat, ProcessInfo, ProcessNode, Progress, ProgressCallback};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+}
+
+pub fn print_processes(processes: &[ProcessInfo], format: Format) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    match format {
+        Format::Table => {
+            writeln!(out, "{:<12} {:>7} {:>7} {:>5} {:>5} {:>9} {:>9} {:<8} {:<5} {:<24} {:>10} COMMAND", "USER", "PID", "PPID", "%CPU", "%MEM", "VSZ", "RSS", "TTY", "STAT", "START", "TIME")?;
+            for p in processes {
+                writeln!(
+                    out,
+                    "{:<12} {:>7} {:>7} {:>5} {:>5} {:>9} {:>9} {:<8} {:<5} {:<24} {:>10} {}",
+                    p.user, p.pid, p.ppid, or_dash(p.cpu), or_dash(p.mem), or_dash(p.vsz), or_dash(p.rss),
+                    p.tty, p.stat, p.start.as_deref().unwrap_or("-"), p.time, p.command,
+                )?;
+            }
+        }
+        Format::Json => print_json(&mut out, processes)?,
+        Format::Ndjson => print_ndjson(&mut out, processes)?,
+        Format::Csv => {
+            writeln!(out, "user,pid,ppid,cpu,mem,vsz,rss,tty,stat,start,time,command")?;
+            for p in processes {
+                let record = [
+                    p.user.clone(), p.pid.to_string(), p.ppid.to_string(), or_empty(p.cpu), or_empty(p.mem), or_empty(p.vsz), or_empty(p.rss),
+                    p.tty.clone(), p.stat.clone(), p.start.clone().unwrap_or_default(), p.time.clone(), p.command.clone(),
+                ];
+                writeln!(out, "{}", record.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn print_process_tree(roots: &[ProcessNode], format: Format) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    match format {
+        Format::Json => print_json(&mut out, roots),
+        Format::Ndjson => print_ndjson(&mut out, roots),
+
+        // csv has no notion of nesting, the ppid column is enough to rebuild the tree
+        Format::Csv => {
+            drop(out);
+            print_processes(&flatten(roots), format)
+        }
+        Format::Table => {
+            for root in roots {
+                writeln!(out, "{} {}", root.process.pid, root.process.command)?;
+                print_children(&mut out, &root.children, "")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_children(out: &mut impl Write, children: &[ProcessNode], prefix: &str) -> io::Result<()> {
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        writeln!(out, "{}{}{} {}", prefix, if last { "└─ " } else { "├─ " }, child.process.pid, child.process.command)?;
+        print_children(out, &child.children, &format!("{}{}", prefix, if last { "   " } else { "│  " }))?;
+    }
+    Ok(())
+}
+
+fn flatten(nodes: &[ProcessNode]) -> Vec<ProcessInfo> {
+    nodes.iter().flat_map(|node| std::iter::once(node.process.clone()).chain(flatten(&node.children))).collect()
+}
+
+/// A progress bar on stderr when it is a terminal, one JSON event per line otherwise.
+pub fn progress_callback() -> ProgressCallback {
+    if io::stderr().is_terminal() {
+        ProgressCallback::new(Duration::from_millis(200), |p| {
+            let _ = print_progress_bar(&mut io::stderr().lock(), p);
+        })
+    } else {
+        ProgressCallback::new(Duration::from_secs(1), |p| {
+            let event = json!({
+                "path": p.path,
+                "bytes": p.bytes,
+                "total": p.total,
+                "elapsed": p.elapsed.as_secs_f64(),
+                "rate": p.rate,
+                "eta": p.eta.map(|eta| eta.as_secs_f64()),
+                "done": p.done,
+            });
+            let _ = writeln!(io::stderr().lock(), "{}", event);
+        })
+    }
+}
+
+fn print_progress_bar(out: &mut impl Write, p: &Progress) -> io::Result<()> {
+    const WIDTH: usize = 30;
+
+    let name = p.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let (bar, percent) = match p.total {
+        Some(total) if total > 0 => {
+            let ratio = (p.bytes as f64 / total as f64).min(1.0);
+            let filled = (ratio * WIDTH as f64) as usize;
+            (format!("{}{}", "#".repeat(filled), "-".repeat(WIDTH - filled)), format!("{:>3}%", (ratio * 100.0) as u32))
+        }
+        _ => ("?".repeat(WIDTH), "  ?%".to_string()),
+    };
+    let eta = p.eta.map_or("--:--".to_string(), |eta| format!("{:02}:{:02}", eta.as_secs() / 60, eta.as_secs() % 60));
+
+    // \x1b[K clears what a longer previous line left behind
+    write!(out, "\r{} [{}] {} {} {}/s eta {}\x1b[K", name, bar, percent, human_bytes(p.bytes as f64), human_bytes(p.rate), eta)?;
+    if p.done {
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+/// Names one per line, or `ls -l` style with numeric owners and UTC mtimes.
+pub fn print_listing(stats: &[FileStat], long: bool) {
+    if !long {
+        for stat in stats {
+            println!("{}", stat.path.display());
+        }
+        return;
+    }
+
+    let size_width = stats.iter().map(|stat| or_dash(stat.len).len()).max().unwrap_or(0);
+    for stat in stats {
+        let target = stat.target.as_ref().map(|target| format!(" -> {}", target.display())).unwrap_or_default();
+        println!(
+            "{} {:>5} {:>5} {:>size_width$} {} {}{}",
+            mode_string(stat.kind, stat.mode), or_dash(stat.uid), or_dash(stat.gid), or_dash(stat.len),
+            stat.modified.map_or("-".to_string(), format_time), stat.path.display(), target,
+        );
+    }
+}
+
+/// `drwxr-xr-x` style, with setuid, setgid and sticky bits shown the way ls does.
+pub fn mode_string(kind: FileKind, mode: Option<u32>) -> String {
+    let kind = match kind {
+        FileKind::File => '-',
+        FileKind::Dir => 'd',
+        FileKind::Symlink => 'l',
+        FileKind::Fifo => 'p',
+        FileKind::Socket => 's',
+        FileKind::BlockDevice => 'b',
+        FileKind::CharDevice => 'c',
+        FileKind::Unknown => '?',
+    };
+    let Some(mode) = mode else {
+        return format!("{}?????????", kind);
+    };
+
+    let mut out = String::from(kind);
+    for (shift, special, set, unset) in [(6, 0o4000, 's', 'S'), (3, 0o2000, 's', 'S'), (0, 0o1000, 't', 'T')] {
+        let bits = mode >> shift & 0o7;
+        out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        out.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+pub fn print_stat(stat: &FileStat) {
+    match &stat.target {
+        Some(target) => println!("  File: {} -> {}", stat.path.display(), target.display()),
+        None => println!("  File: {}", stat.path.display()),
+    }
+    println!("  Type: {}", serde_json::to_value(stat.kind).ok().and_then(|kind| kind.as_str().map(str::to_string)).unwrap_or_default());
+    println!("  Size: {}", or_dash(stat.len));
+    println!("  Mode: {}", stat.mode.map_or("-".to_string(), |mode| format!("{:04o}", mode)));
+    println!("   Uid: {}  Gid: {}", or_dash(stat.uid), or_dash(stat.gid));
+    println!("Access: {}", stat.accessed.map_or("-".to_string(), format_time));
+    println!("Modify: {}", stat.modified.map_or("-".to_string(), format_time));
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC, the remote timezone is unknown anyway.
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (days, rest) = (secs / 86400, secs % 86400);
+
+    // civil from days, Howard Hinnant's algorithm shifted to years starting in March
+
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+pub fn print_json(out: &mut impl Write, value: &(impl Serialize + ?Sized)) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)
+}
+
+pub fn print_ndjson<T: Serialize>(out: &mut impl Write, values: &[T]) -> io::Result<()> {
+    for value in values {
+        serde_json::to_writer(&mut *out, value)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn or_dash(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+fn or_empty(value: Option<impl ToString>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn mode_strings() {
+        assert_eq!(mode_string(FileKind::File, Some(0o644)), "-rw-r--r--");
+        assert_eq!(mode_string(FileKind::Dir, Some(0o755)), "drwxr-xr-x");
+        assert_eq!(mode_string(FileKind::File, Some(0o4755)), "-rwsr-xr-x");
+        assert_eq!(mode_string(FileKind::File, Some(0o4644)), "-rwSr--r--");
+        assert_eq!(mode_string(FileKind::Dir, Some(0o2775)), "drwxrwsr-x");
+        assert_eq!(mode_string(FileKind::Dir, Some(0o2745)), "drwxr-Sr-x");
+        assert_eq!(mode_string(FileKind::Dir, Some(0o1777)), "drwxrwxrwt");
+        assert_eq!(mode_string(FileKind::Dir, Some(0o1776)), "drwxrwxrwT");
+        assert_eq!(mode_string(FileKind::Symlink, Some(0o777)), "lrwxrwxrwx");
+        assert_eq!(mode_string(FileKind::Socket, None), "s?????????");
+    }
+
+    #[test]
+    fn times() {
+        let at = |secs| format_time(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01 00:00:00");
+        assert_eq!(at(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(at(1_709_251_199), "2024-02-29 23:59:59");
+        assert_eq!(at(1_709_251_200), "2024-03-01 00:00:00");
+
+        // not a leap year, divisible by 100 but not by 400
+        assert_eq!(at(4_107_456_000), "2100-02-28 00:00:00");
+        assert_eq!(at(4_107_542_400), "2100-03-01 00:00:00");
+        assert_eq!(at(253_402_300_799), "9999-12-31 23:59:59");
+
+        // sftp can't express times before the epoch, they show as the epoch
+        assert_eq!(format_time(UNIX_EPOCH - Duration::from_secs(1)), "1970-01-01 00:00:00");
+    }
+}
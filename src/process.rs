@@ -0,0 +1,493 @@
+use std::{cmp::Ordering, collections::{HashMap, HashSet}, time::Duration};
+use openssh::Stdio;
+use tokio::time::{interval, Instant};
+use regex::Regex;
+use serde::Serialize;
+use tokio::io::{BufReader, AsyncBufReadExt};
+use crate::{client::{Client, check_status}, error::{Error, RemoteStatus, Result}};
+
+/// One line of remote `ps` output.
+///
+/// Columns BusyBox `ps` can't report are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessInfo {
+    pub user: String,
+    pub pid: u32,
+    pub ppid: u32,
+    pub cpu: Option<f32>,
+    pub mem: Option<f32>,
+
+    // in KiB
+    pub vsz: Option<u64>,
+    pub rss: Option<u64>,
+
+    pub tty: String,
+    pub stat: String,
+    pub start: Option<String>,
+    pub time: String,
+    pub command: String,
+}
+
+/// Selects processes; every non-empty criterion has to match.
+#[derive(Debug, Default, Clone)]
+pub struct ProcessFilter {
+    pub users: Vec<String>,
+    pub pids: Vec<u32>,
+
+    // searched anywhere in the command line
+    pub pattern: Option<Regex>,
+}
+
+impl ProcessFilter {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.pids.is_empty() && self.pattern.is_none()
+    }
+
+    pub fn matches(&self, process: &ProcessInfo) -> bool {
+        (self.users.is_empty() || self.users.contains(&process.user))
+            && (self.pids.is_empty() || self.pids.contains(&process.pid))
+            && self.pattern.as_ref().is_none_or(|pattern| pattern.is_match(&process.command))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Cpu,
+    Mem,
+}
+
+impl SortKey {
+
+    // heaviest first, unknown values (BusyBox) sort last
+
+    fn compare(&self, a: &ProcessInfo, b: &ProcessInfo) -> Ordering {
+        let value = |p: &ProcessInfo| match self {
+            Self::Cpu => p.cpu,
+            Self::Mem => p.mem,
+        };
+        value(b).partial_cmp(&value(a)).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Sorts the heaviest processes first.
+pub fn sort_processes(processes: &mut [ProcessInfo], key: SortKey) {
+    processes.sort_by(|a, b| key.compare(a, b));
+}
+
+/// A process together with the processes it spawned.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessNode {
+    #[serde(flatten)]
+    pub process: ProcessInfo,
+    pub children: Vec<ProcessNode>,
+}
+
+/// Links processes to their parents; processes whose parent is not in the list become roots.
+pub fn build_tree(processes: Vec<ProcessInfo>) -> Vec<ProcessNode> {
+    let pids = processes.iter().map(|p| p.pid).collect::<HashSet<_>>();
+    let mut children = HashMap::<u32, Vec<ProcessInfo>>::new();
+    let mut roots = Vec::new();
+    for process in processes {
+
+        // pid 0 is its own parent on some systems
+
+        if process.ppid != process.pid && pids.contains(&process.ppid) {
+            children.entry(process.ppid).or_default().push(process);
+        } else {
+            roots.push(process);
+        }
+    }
+
+    fn attach(process: ProcessInfo, children: &mut HashMap<u32, Vec<ProcessInfo>>) -> ProcessNode {
+        let own = children.remove(&process.pid).unwrap_or_default();
+        ProcessNode {
+            children: own.into_iter().map(|child| attach(child, children)).collect(),
+            process,
+        }
+    }
+
+    roots.into_iter().map(|root| attach(root, &mut children)).collect()
+}
+
+/// Keeps the subtrees rooted at the outermost processes matching `filter`.
+pub fn select_subtrees(nodes: Vec<ProcessNode>, filter: &ProcessFilter) -> Vec<ProcessNode> {
+    let mut selected = Vec::new();
+    for node in nodes {
+        if filter.matches(&node.process) {
+            selected.push(node);
+        } else {
+            selected.extend(select_subtrees(node.children, filter));
+        }
+    }
+    selected
+}
+
+/// Sorts siblings at every level, heaviest first.
+pub fn sort_tree(nodes: &mut [ProcessNode], key: SortKey) {
+    nodes.sort_by(|a, b| key.compare(&a.process, &b.process));
+    for node in nodes {
+        sort_tree(&mut node.children, key);
+    }
+}
+
+// signal in $1, pids after it
+const KILL_SCRIPT: &str = r#"signal=$1; shift; for pid; do kill "$signal" "$pid" 2>/dev/null || echo "$pid"; done"#;
+
+#[derive(Debug, Clone, Copy)]
+enum PsFlavor {
+    // procps and BSD ps, lstart is always five words like "Wed Feb 14 10:00:00 2024"
+    Full,
+
+    // BusyBox ps has no -A, pcpu, pmem nor lstart, and prints large sizes as "12m"
+    BusyBox,
+}
+
+impl PsFlavor {
+    fn argv(&self) -> &'static [&'static str] {
+        match self {
+            Self::Full => &["ps", "-A", "-ww", "-o", "user,pid,ppid,pcpu,pmem,vsz,rss,tty,stat,lstart,time,args"],
+            Self::BusyBox => &["ps", "-o", "user,pid,ppid,vsz,rss,tty,stat,time,args"],
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        match self {
+            Self::Full => 12,
+            Self::BusyBox => 9,
+        }
+    }
+
+    fn parse(&self, line: &str) -> Option<ProcessInfo> {
+        match self {
+            Self::Full => {
+                let (fields, command) = split_fields(line, 15)?;
+                Some(ProcessInfo {
+                    user: fields[0].to_string(),
+                    pid: fields[1].parse().ok()?,
+                    ppid: fields[2].parse().ok()?,
+                    cpu: Some(fields[3].parse().ok()?),
+                    mem: Some(fields[4].parse().ok()?),
+                    vsz: Some(fields[5].parse().ok()?),
+                    rss: Some(fields[6].parse().ok()?),
+                    tty: fields[7].to_string(),
+                    stat: fields[8].to_string(),
+                    start: Some(fields[9..14].join(" ")),
+                    time: fields[14].to_string(),
+                    command: command.to_string(),
+                })
+            }
+            Self::BusyBox => {
+                let (fields, command) = split_fields(line, 8)?;
+                Some(ProcessInfo {
+                    user: fields[0].to_string(),
+                    pid: fields[1].parse().ok()?,
+                    ppid: fields[2].parse().ok()?,
+                    cpu: None,
+                    mem: None,
+                    vsz: Some(parse_busybox_size(fields[3])?),
+                    rss: Some(parse_busybox_size(fields[4])?),
+                    tty: fields[5].to_string(),
+                    stat: fields[6].to_string(),
+                    start: None,
+                    time: fields[7].to_string(),
+                    command: command.to_string(),
+                })
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Sends `signal` (a name like `TERM` or a number) to the given pids with the remote `kill`, returning
+    /// the pids that got it.
+    ///
+    /// A process that exited before its turn is left out rather than failing the call, pattern kills
+    /// race with short lived processes and children that die along with their parent.
+    pub async fn kill(&self, pids: &[u32], signal: &str) -> Result<Vec<u32>> {
+        if pids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // one kill per pid, printing the ones it failed for, so a single exited process can't hide the rest
+
+        let signal = format!("-{}", signal.strip_prefix("SIG").unwrap_or(signal));
+        let describe = |pids: &[u32]| format!("kill {} {}", signal, pids.iter().map(u32::to_string).collect::<Vec<_>>().join(" "));
+        let mut argv = vec!["sh".to_string(), "-c".to_string(), KILL_SCRIPT.to_string(), "sh".to_string(), signal.clone()];
+        argv.extend(pids.iter().map(u32::to_string));
+        let output = self.command(&argv).stdin(Stdio::null()).stderr(Stdio::null()).output().await?;
+        check_status(&describe(pids), Ok(output.status))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let failed = stdout.lines().map(|line| line.parse::<u32>().map_err(|_| Error::parse("kill output", line))).collect::<Result<HashSet<_>>>()?;
+        if failed.is_empty() {
+            return Ok(pids.to_vec());
+        }
+
+        // exited by now is fine, still there means kill was refused
+
+        let alive = self.processes().await?.into_iter().map(|p| p.pid).filter(|pid| failed.contains(pid)).collect::<Vec<_>>();
+        if !alive.is_empty() {
+            return Err(Error::RemoteCommand { command: describe(&alive), status: RemoteStatus::Code(1) });
+        }
+        Ok(pids.iter().copied().filter(|pid| !failed.contains(pid)).collect())
+    }
+
+    /// Signals every process matching `filter` and returns the ones that got the signal.
+    ///
+    /// An empty filter matches everything, callers should make sure that is what they want.
+    pub async fn signal_processes(&self, filter: &ProcessFilter, signal: &str) -> Result<Vec<ProcessInfo>> {
+        let matched = self.processes().await?.into_iter().filter(|p| filter.matches(p)).collect::<Vec<_>>();
+        let signalled = self.kill(&matched.iter().map(|p| p.pid).collect::<Vec<_>>(), signal).await?;
+        Ok(matched.into_iter().filter(|p| signalled.contains(&p.pid)).collect())
+    }
+
+    /// Polls the process list until none of `pids` is left.
+    pub async fn wait_for_exit(&self, pids: &[u32], poll: Duration, max_wait: Option<Duration>) -> Result<()> {
+        let deadline = max_wait.map(|max_wait| Instant::now() + max_wait);
+        let mut interval = interval(poll);
+
+        loop {
+            interval.tick().await;
+            let alive = self.processes().await?.into_iter().map(|p| p.pid).filter(|pid| pids.contains(pid)).collect::<Vec<_>>();
+            if alive.is_empty() {
+                return Ok(());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::WaitTimeout { pids: alive });
+            }
+        }
+    }
+
+    pub async fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        match self.processes_with(PsFlavor::Full).await {
+            Err(Error::RemoteCommand { .. }) => self.processes_with(PsFlavor::BusyBox).await,
+            result => result,
+        }
+    }
+
+    async fn processes_with(&self, flavor: PsFlavor) -> Result<Vec<ProcessInfo>> {
+        let argv = flavor.argv();
+        let mut ps_process = self.command(argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .await?;
+
+        let stdout = ps_process.stdout().take().expect("should be piped one");
+        let mut line_stream = BufReader::new(stdout).lines();
+
+        let mut lines = Vec::new();
+        while let Some(line) = line_stream.next_line().await? {
+            lines.push(line);
+        }
+
+        // check the status first, an unsupported option shows up as garbage output
+        check_status(&argv.join(" "), ps_process.wait().await)?;
+
+        let mut lines = lines.into_iter();
+        let header = lines.next().unwrap_or_default();
+        if header.split_whitespace().count() != flavor.header_len() {
+            return Err(Error::parse("ps header", header));
+        }
+
+        lines.map(|line| flavor.parse(&line).ok_or_else(|| Error::parse("ps record", line))).collect()
+    }
+}
+
+fn split_fields(line: &str, n: usize) -> Option<(Vec<&str>, &str)> {
+
+    // the last column may contain spaces, so only the first n are split off
+
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Some((fields, rest))
+}
+
+fn parse_busybox_size(field: &str) -> Option<u64> {
+    let (number, unit) = match field.char_indices().last()? {
+        (i, 'm') => (&field[..i], 1024),
+        (i, 'g') => (&field[..i], 1024 * 1024),
+        _ => (field, 1),
+    };
+    Some(number.parse::<u64>().ok()? * unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCPS: &str = "\
+USER         PID    PPID %CPU %MEM    VSZ   RSS TT       STAT                  STARTED     TIME COMMAND
+root           1       0  0.0  0.1 167744 12960 ?        Ss   Sun Feb  4 09:12:01 2024 00:00:03 /sbin/init splash
+www-data    2841    2790  1.5  2.3 912384 187620 pts/0   Sl+  Mon Feb 12 17:45:30 2024 00:12:47 php-fpm: pool www  (worker)";
+
+    const MACOS: &str = "\
+USER               PID  PPID  %CPU %MEM      VSZ    RSS   TT  STAT STARTED                      TIME COMMAND
+_windowserver      157     1  12.3  0.9 412345678  78900   ??  Ss   Mon Feb  5 08:01:22 2024  45:12.34 /System/Library/PrivateFrameworks/SkyLight.framework/Resources/WindowServer -daemon";
+
+    const BUSYBOX: &str = "\
+USER       PID  PPID VSZ  RSS  TT     STAT TIME  COMMAND
+root         1     0 1640    4 ?      S     0:01 init
+root      2345     1   1g  12m pts/0  S     1:02 /usr/bin/dockerd --config /etc/docker.json";
+
+    fn parse(flavor: PsFlavor, output: &str) -> Vec<ProcessInfo> {
+        let mut lines = output.lines();
+        assert_eq!(lines.next().unwrap().split_whitespace().count(), flavor.header_len());
+        lines.map(|line| flavor.parse(line).unwrap_or_else(|| panic!("unparsed: {line}"))).collect()
+    }
+
+    #[test]
+    fn procps() {
+        let processes = parse(PsFlavor::Full, PROCPS);
+        assert_eq!(processes[0], ProcessInfo {
+            user: "root".into(),
+            pid: 1,
+            ppid: 0,
+            cpu: Some(0.0),
+            mem: Some(0.1),
+            vsz: Some(167744),
+            rss: Some(12960),
+            tty: "?".into(),
+            stat: "Ss".into(),
+            start: Some("Sun Feb 4 09:12:01 2024".into()),
+            time: "00:00:03".into(),
+            command: "/sbin/init splash".into(),
+        });
+
+        // spaces inside the command survive, only the columns before it are split
+        assert_eq!(processes[1].command, "php-fpm: pool www  (worker)");
+        assert_eq!(processes[1].start.as_deref(), Some("Mon Feb 12 17:45:30 2024"));
+        assert_eq!(processes[1].rss, Some(187620));
+    }
+
+    #[test]
+    fn macos() {
+        let processes = parse(PsFlavor::Full, MACOS);
+        assert_eq!(processes[0].user, "_windowserver");
+        assert_eq!(processes[0].cpu, Some(12.3));
+        assert_eq!(processes[0].vsz, Some(412345678));
+        assert_eq!(processes[0].tty, "??");
+        assert_eq!(processes[0].start.as_deref(), Some("Mon Feb 5 08:01:22 2024"));
+        assert_eq!(processes[0].time, "45:12.34");
+        assert_eq!(processes[0].command, "/System/Library/PrivateFrameworks/SkyLight.framework/Resources/WindowServer -daemon");
+    }
+
+    #[test]
+    fn busybox() {
+        let processes = parse(PsFlavor::BusyBox, BUSYBOX);
+        assert_eq!(processes[0], ProcessInfo {
+            user: "root".into(),
+            pid: 1,
+            ppid: 0,
+            cpu: None,
+            mem: None,
+            vsz: Some(1640),
+            rss: Some(4),
+            tty: "?".into(),
+            stat: "S".into(),
+            start: None,
+            time: "0:01".into(),
+            command: "init".into(),
+        });
+        assert_eq!(processes[1].vsz, Some(1024 * 1024));
+        assert_eq!(processes[1].rss, Some(12 * 1024));
+        assert_eq!(processes[1].command, "/usr/bin/dockerd --config /etc/docker.json");
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert_eq!(PsFlavor::Full.parse("root 1 0 0.0 0.1 167744 12960 ? Ss Sun Feb 4"), None);
+        assert_eq!(PsFlavor::Full.parse("root x 0 0.0 0.1 167744 12960 ? Ss Sun Feb 4 09:12:01 2024 00:00:03 init"), None);
+        assert_eq!(PsFlavor::BusyBox.parse("root 1 0 12k 4 ? S 0:01 init"), None);
+        assert_eq!(parse_busybox_size(""), None);
+        assert_eq!(parse_busybox_size("m"), None);
+    }
+
+    fn process(pid: u32, ppid: u32, command: &str) -> ProcessInfo {
+        ProcessInfo {
+            user: "root".into(),
+            pid,
+            ppid,
+            cpu: None,
+            mem: None,
+            vsz: None,
+            rss: None,
+            tty: "?".into(),
+            stat: "S".into(),
+            start: None,
+            time: "0:00".into(),
+            command: command.into(),
+        }
+    }
+
+    // pid with its children, depth first
+    fn shape(nodes: &[ProcessNode]) -> Vec<(u32, Vec<u32>)> {
+        let mut links = Vec::new();
+        for node in nodes {
+            links.push((node.process.pid, node.children.iter().map(|child| child.process.pid).collect()));
+            links.extend(shape(&node.children));
+        }
+        links
+    }
+
+    #[test]
+    fn tree_links_children_to_parents() {
+        let tree = build_tree(vec![
+            process(0, 0, "[kernel]"),
+            process(1, 0, "init"),
+            process(2, 0, "kthreadd"),
+            process(100, 1, "sshd"),
+            process(200, 100, "bash"),
+            process(201, 100, "bash"),
+            process(300, 200, "vim"),
+        ]);
+
+        // pid 0 is its own parent and must not end up as its own child
+        assert_eq!(shape(&tree), [
+            (0, vec![1, 2]),
+            (1, vec![100]),
+            (100, vec![200, 201]),
+            (200, vec![300]),
+            (300, vec![]),
+            (201, vec![]),
+            (2, vec![]),
+        ]);
+    }
+
+    #[test]
+    fn orphans_become_roots() {
+        // BusyBox ps without -A, or a parent that exited between fork and the listing
+        let tree = build_tree(vec![process(500, 42, "worker"), process(501, 500, "child"), process(600, 43, "other")]);
+        assert_eq!(shape(&tree), [(500, vec![501]), (501, vec![]), (600, vec![])]);
+    }
+
+    #[test]
+    fn selection_keeps_outermost_matches() {
+        let tree = build_tree(vec![
+            process(1, 0, "init"),
+            process(10, 1, "nginx: master"),
+            process(11, 10, "nginx: worker"),
+            process(12, 10, "logger"),
+            process(20, 1, "cron"),
+            process(21, 20, "sh -c nginx -s reload"),
+        ]);
+        let filter = ProcessFilter { pattern: Some(Regex::new("nginx").unwrap()), ..Default::default() };
+        let selected = select_subtrees(tree, &filter);
+
+        // the master comes with all its children, matching or not; the nested worker isn't repeated
+        assert_eq!(shape(&selected), [(10, vec![11, 12]), (11, vec![]), (12, vec![]), (21, vec![])]);
+    }
+
+    #[test]
+    fn selection_of_nothing_is_empty() {
+        let tree = build_tree(vec![process(1, 0, "init"), process(2, 1, "sshd")]);
+        let filter = ProcessFilter { pids: vec![99], ..Default::default() };
+        assert!(select_subtrees(tree, &filter).is_empty());
+    }
+}
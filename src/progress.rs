@@ -0,0 +1,143 @@
+use std::{fmt, io, path::PathBuf, pin::Pin, sync::Arc, task::{ready, Context, Poll}, time::Duration};
+use tokio::{io::{AsyncRead, AsyncWrite, ReadBuf}, time::Instant};
+
+/// A snapshot of one transfer, handed to [`ProgressCallback`].
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub path: PathBuf,
+
+    /// Bytes of the destination written so far, including a resumed prefix
+    pub bytes: u64,
+
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+
+    /// Bytes per second moved by this transfer, a resumed prefix does not count
+    pub rate: f64,
+
+    pub eta: Option<Duration>,
+
+    /// Set on the last report of a transfer
+    pub done: bool,
+}
+
+/// Receives [`Progress`] reports at most once per `interval`, plus once when the transfer ends.
+#[derive(Clone)]
+pub struct ProgressCallback {
+    callback: Arc<dyn Fn(&Progress) + Send + Sync>,
+    interval: Duration,
+}
+
+impl ProgressCallback {
+    pub fn new(interval: Duration, callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self { callback: Arc::new(callback), interval }
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressCallback").field("interval", &self.interval).finish_non_exhaustive()
+    }
+}
+
+/// Counts bytes going through a reader or writer and reports them to a callback.
+///
+/// Reads report completion at end of stream, writes on shutdown.
+#[derive(Debug)]
+pub struct ProgressStream<S> {
+    inner: S,
+    state: Option<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    callback: ProgressCallback,
+    path: PathBuf,
+    offset: u64,
+    bytes: u64,
+    total: Option<u64>,
+    start: Instant,
+    last: Instant,
+    done: bool,
+}
+
+impl<S> ProgressStream<S> {
+    /// `offset` is where the transfer starts, for resumed transfers. Without a callback this is a plain pass-through.
+    pub fn new(inner: S, callback: Option<ProgressCallback>, path: impl Into<PathBuf>, offset: u64, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        let state = callback.map(|callback| State { callback, path: path.into(), offset, bytes: offset, total, start: now, last: now, done: false });
+        Self { inner, state }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn advance(&mut self, n: usize) {
+        if let Some(state) = &mut self.state {
+            state.bytes += n as u64;
+            if state.last.elapsed() >= state.callback.interval {
+                state.report(false);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(state) = &mut self.state {
+            if !state.done {
+                state.done = true;
+                state.report(true);
+            }
+        }
+    }
+}
+
+impl State {
+    fn report(&mut self, done: bool) {
+        self.last = Instant::now();
+        let elapsed = self.start.elapsed();
+        let moved = self.bytes - self.offset;
+        let rate = if elapsed.is_zero() { 0.0 } else { moved as f64 / elapsed.as_secs_f64() };
+        let eta = match self.total {
+            _ if done => Some(Duration::ZERO),
+            Some(total) if rate > 0.0 => Some(Duration::from_secs_f64(total.saturating_sub(self.bytes) as f64 / rate)),
+            _ => None,
+        };
+        (self.callback.callback)(&Progress { path: self.path.clone(), bytes: self.bytes, total: self.total, elapsed, rate, eta, done });
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ProgressStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - filled;
+
+        // a read that could have returned data but didn't is the end of the stream
+
+        if n == 0 && buf.remaining() > 0 {
+            self.finish();
+        } else {
+            self.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ProgressStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.advance(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(Pin::new(&mut self.inner).poll_shutdown(cx))?;
+        self.finish();
+        Poll::Ready(Ok(()))
+    }
+}
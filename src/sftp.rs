@@ -0,0 +1,85 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path, sync::Arc};
+use openssh::{Child, Session, Stdio};
+use openssh_sftp_client::{Sftp, metadata::{MetaData, Permissions}};
+use crate::error::Result;
+
+// sftp-client only exposes permissions bit by bit, these convert from and to the usual mode_t bits;
+// the setters are wrapped since later 0.14 releases return `&mut Self` from them
+
+type ModeBit = (u32, fn(&Permissions) -> bool, fn(&mut Permissions, bool));
+
+const MODE_BITS: [ModeBit; 12] = [
+    (0o4000, Permissions::suid, |p, v| { p.set_suid(v); }),
+    (0o2000, Permissions::sgid, |p, v| { p.set_sgid(v); }),
+    (0o1000, Permissions::svtx, |p, v| { p.set_vtx(v); }),
+    (0o400, Permissions::read_by_owner, |p, v| { p.set_read_by_owner(v); }),
+    (0o200, Permissions::write_by_owner, |p, v| { p.set_write_by_owner(v); }),
+    (0o100, Permissions::execute_by_owner, |p, v| { p.set_execute_by_owner(v); }),
+    (0o040, Permissions::read_by_group, |p, v| { p.set_read_by_group(v); }),
+    (0o020, Permissions::write_by_group, |p, v| { p.set_write_by_group(v); }),
+    (0o010, Permissions::execute_by_group, |p, v| { p.set_execute_by_group(v); }),
+    (0o004, Permissions::read_by_other, |p, v| { p.set_read_by_other(v); }),
+    (0o002, Permissions::write_by_other, |p, v| { p.set_write_by_other(v); }),
+    (0o001, Permissions::execute_by_other, |p, v| { p.set_execute_by_other(v); }),
+];
+
+pub fn mode_of(permissions: &Permissions) -> u32 {
+    MODE_BITS.iter().filter(|(_, get, _)| get(permissions)).map(|(bit, _, _)| bit).sum()
+}
+
+pub fn permissions_from_mode(mode: u32) -> Permissions {
+    let mut permissions = Permissions::new();
+    for (bit, _, set) in MODE_BITS {
+        set(&mut permissions, mode & bit != 0);
+    }
+    permissions
+}
+
+/// Copies mode and mtime of a remote file onto a local one, as far as the server reported them.
+pub(crate) fn apply_metadata(local_path: &Path, metadata: &MetaData) -> Result<()> {
+    if let Some(permissions) = metadata.permissions() {
+        fs::set_permissions(local_path, fs::Permissions::from_mode(mode_of(&permissions)))?;
+    }
+    if let Some(modified) = metadata.modified() {
+        fs::File::options().write(true).open(local_path)?.set_modified(modified.as_system_time())?;
+    }
+    Ok(())
+}
+
+/// An sftp channel together with the remote subsystem process serving it.
+#[derive(Debug)]
+pub(crate) struct SftpHandle {
+    sftp: Sftp,
+    process: Child<Arc<Session>>,
+}
+
+impl SftpHandle {
+    pub(crate) async fn open(session: Arc<Session>) -> Result<Self> {
+
+        // an owned session reference lets the child live next to the session in Client
+
+        let mut process = Session::to_subsystem(session, "sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await?;
+
+        let sftp = Sftp::new(
+            process.stdin().take().expect("should be piped"),
+            process.stdout().take().expect("should be piped"),
+            Default::default(),
+        ).await?;
+
+        Ok(Self { sftp, process })
+    }
+
+    pub(crate) fn sftp(&self) -> &Sftp {
+        &self.sftp
+    }
+
+    pub(crate) async fn close(self) -> Result<()> {
+        self.sftp.close().await?;
+        self.process.wait().await?;
+        Ok(())
+    }
+}
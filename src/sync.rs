@@ -0,0 +1,275 @@
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use futures::{StreamExt, stream};
+use tokio::fs::File;
+use crate::{client::{Client, PutOptions}, error::{Error, Result}, glob::Glob, hash::{HashAlgorithm, Hasher}, sftp::permissions_from_mode, transfer::{EntryKind, LocalEntry, RemoteEntry, TransferReport, walk_local}};
+
+#[derive(Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Compare content hashes of files with equal size instead of their mtimes
+    pub checksum: Option<HashAlgorithm>,
+
+    /// Remove remote entries that don't exist locally, ones excluded or outside the includes are left alone
+    pub delete: bool,
+
+    /// When set, only files matching one of these are synced
+    pub include: Vec<Glob>,
+
+    /// Entries matching any of these are skipped, along with everything below an excluded directory
+    pub exclude: Vec<Glob>,
+
+    /// Work out what would change without changing anything
+    pub dry_run: bool,
+
+    /// Applied to every upload
+    pub put: PutOptions,
+}
+
+/// What a sync did, or would have done with `dry_run`.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub uploaded: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+    pub unchanged: usize,
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl Client {
+    /// Makes `remote_dir` match `local_dir`, uploading only files that differ.
+    ///
+    /// Files differ when their sizes or mtimes do, like rsync. Uploads get the mtime of their source, so
+    /// an older local copy restored from a backup is sent as well.
+    pub async fn sync_dir(&self, local_dir: &Path, remote_dir: &Path, concurrency: usize, options: &SyncOptions) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        let walked = walk_local(local_dir).await?;
+        let local = walked.iter().filter(|entry| options.selects(&entry.path, matches!(entry.kind, EntryKind::Dir))).collect::<Vec<_>>();
+
+        let mut remote = HashMap::new();
+        if self.sftp().await?.fs().metadata(remote_dir).await.is_ok() {
+            let mut walk_report = TransferReport::default();
+            for entry in self.walk_remote(remote_dir, &mut walk_report).await? {
+                remote.insert(entry.path.clone(), entry);
+            }
+            report.failed.append(&mut walk_report.failed);
+        } else if !options.dry_run {
+            self.create_dir_all(remote_dir).await?;
+        }
+
+        // directories and symlinks first and in walk order, so every upload has its parent
+
+        let mut fs = self.sftp().await?.fs();
+        let mut files = Vec::new();
+        for &entry in &local {
+            let remote_path = remote_dir.join(&entry.path);
+            let existing = remote.get(&entry.path);
+            let result = match &entry.kind {
+                EntryKind::Dir => match existing.map(|existing| &existing.kind) {
+                    Some(EntryKind::Dir) => continue,
+                    _ if options.dry_run => continue,
+                    other => async {
+                        if other.is_some() {
+                            fs.remove_file(&remote_path).await?;
+                        }
+                        fs.create_dir(&remote_path).await.map_err(|e| Error::sftp_at(&remote_path, e))?;
+                        fs.set_permissions(&remote_path, permissions_from_mode(entry.mode)).await?;
+                        Ok::<_, Error>(())
+                    }.await,
+                },
+                EntryKind::Symlink(target) => match existing.map(|existing| &existing.kind) {
+                    Some(EntryKind::Symlink(existing)) if existing == target => {
+                        report.unchanged += 1;
+                        continue;
+                    }
+                    _ if options.dry_run => Ok(()),
+                    other => async {
+                        if other.is_some() {
+                            fs.remove_file(&remote_path).await?;
+                        }
+                        fs.symlink(target, &remote_path).await?;
+                        Ok::<_, Error>(())
+                    }.await,
+                },
+                EntryKind::File => {
+                    files.push(entry);
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) if matches!(entry.kind, EntryKind::Symlink(_)) => report.uploaded.push(entry.path.clone()),
+                Ok(()) => {}
+                Err(e) => report.failed.push((remote_path, e)),
+            }
+        }
+
+        let results = stream::iter(files)
+            .map(|entry| {
+                let existing = remote.get(&entry.path);
+                async move {
+                    let local_path = local_dir.join(&entry.path);
+                    let remote_path = remote_dir.join(&entry.path);
+                    let result = async {
+                        if !self.differs(&local_path, &remote_path, entry.len, entry.modified, existing, options.checksum).await? {
+                            return Ok(false);
+                        }
+                        if !options.dry_run {
+                            if let Some(RemoteEntry { kind: EntryKind::Dir | EntryKind::Symlink(_), .. }) = existing {
+                                self.remove(&remote_path, options.delete).await?;
+                            }
+                            self.put_file(&local_path, &remote_path, &options.put).await?;
+                            if let Some(modified) = entry.modified {
+                                self.set_modified(&remote_path, modified).await?;
+                            }
+                        }
+                        Ok(true)
+                    }.await;
+                    (entry.path.clone(), remote_path, result)
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        for (path, remote_path, result) in results {
+            match result {
+                Ok(true) => report.uploaded.push(path),
+                Ok(false) => report.unchanged += 1,
+                Err(e) => report.failed.push((remote_path, e)),
+            }
+        }
+
+        if options.delete {
+            for entry in options.extraneous(&walked, &remote) {
+                let remote_path = remote_dir.join(&entry.path);
+                let result = match entry.kind {
+                    _ if options.dry_run => Ok(()),
+                    EntryKind::Dir => fs.remove_dir(&remote_path).await.map_err(Error::from),
+                    _ => fs.remove_file(&remote_path).await.map_err(Error::from),
+                };
+                match result {
+                    Ok(()) => report.deleted.push(entry.path.clone()),
+                    Err(e) => report.failed.push((remote_path, e)),
+                }
+            }
+        }
+
+        report.uploaded.sort();
+        Ok(report)
+    }
+
+    async fn differs(&self, local_path: &Path, remote_path: &Path, len: u64, modified: Option<SystemTime>, existing: Option<&RemoteEntry>, checksum: Option<HashAlgorithm>) -> Result<bool> {
+        let Some(RemoteEntry { kind: EntryKind::File, metadata, .. }) = existing else {
+            return Ok(true);
+        };
+        if metadata.len() != Some(len) {
+            return Ok(true);
+        }
+        if let Some(algorithm) = checksum {
+            let mut hasher = Hasher::new(algorithm);
+            hasher.update_from(&mut File::open(local_path).await?, len).await?;
+            return Ok(hasher.finalize() != self.remote_hash(remote_path, algorithm).await?);
+        }
+
+        // remote mtimes only have whole seconds
+
+        let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        Ok(match (modified, metadata.modified()) {
+            (Some(local), Some(remote)) => seconds(local) != seconds(remote.as_system_time()),
+            _ => true,
+        })
+    }
+}
+
+impl SyncOptions {
+    /// Remote entries `delete` removes, children before their parents so directories are empty once it's their turn.
+    ///
+    /// `local` is the whole local walk: whatever exists locally stays, synced or not. Remote entries this
+    /// sync doesn't select are left alone, and so are the directories holding them.
+    fn extraneous<'a>(&self, local: &[LocalEntry], remote: &'a HashMap<PathBuf, RemoteEntry>) -> Vec<&'a RemoteEntry> {
+        let wanted = local.iter().map(|entry| &entry.path).collect::<HashSet<_>>();
+        let (extraneous, kept): (Vec<_>, Vec<_>) = remote.values()
+            .filter(|entry| !wanted.contains(&entry.path))
+            .partition(|entry| self.selects(&entry.path, matches!(entry.kind, EntryKind::Dir)));
+        let holding = kept.iter().flat_map(|entry| entry.path.ancestors().skip(1)).collect::<HashSet<_>>();
+
+        let mut extraneous = extraneous.into_iter().filter(|entry| !holding.contains(entry.path.as_path())).collect::<Vec<_>>();
+        extraneous.sort_by(|a, b| b.path.components().count().cmp(&a.path.components().count()).then_with(|| a.path.cmp(&b.path)));
+        extraneous
+    }
+
+    fn selects(&self, path: &Path, is_dir: bool) -> bool {
+        if self.excludes(path).is_some() {
+            return false;
+        }
+
+        // directories are always walked, includes pick the files inside them
+
+        let path = path.to_string_lossy();
+        is_dir || self.include.is_empty() || self.include.iter().any(|glob| glob.matches_path(&path))
+    }
+
+    fn excludes(&self, path: &Path) -> Option<&Glob> {
+        path.ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .find_map(|ancestor| {
+                let ancestor = ancestor.to_string_lossy();
+                self.exclude.iter().find(|glob| glob.matches_path(&ancestor))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssh_sftp_client::metadata::MetaDataBuilder;
+    use super::*;
+
+    fn local(path: &str, kind: EntryKind) -> LocalEntry {
+        LocalEntry { path: path.into(), kind, mode: 0o644, len: 0, modified: None }
+    }
+
+    fn remote(entries: Vec<(&str, EntryKind)>) -> HashMap<PathBuf, RemoteEntry> {
+        entries.into_iter()
+            .map(|(path, kind)| (PathBuf::from(path), RemoteEntry { path: path.into(), kind, metadata: MetaDataBuilder::new().create() }))
+            .collect()
+    }
+
+    fn deleted(options: &SyncOptions, local: &[LocalEntry], remote: &HashMap<PathBuf, RemoteEntry>) -> Vec<String> {
+        options.extraneous(local, remote).iter().map(|entry| entry.path.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn delete_removes_children_before_parents() {
+        let options = SyncOptions { delete: true, ..Default::default() };
+        let local = [local("src", EntryKind::Dir), local("src/main.rs", EntryKind::File)];
+        let remote = remote(vec![
+            ("src", EntryKind::Dir),
+            ("src/main.rs", EntryKind::File),
+            ("old", EntryKind::Dir),
+            ("old/gone.rs", EntryKind::File),
+        ]);
+        assert_eq!(deleted(&options, &local, &remote), ["old/gone.rs", "old"]);
+    }
+
+    #[test]
+    fn delete_keeps_local_files_outside_include() {
+        let options = SyncOptions { delete: true, include: vec![Glob::new("*.rs").unwrap()], ..Default::default() };
+        let local = [local("Cargo.toml", EntryKind::File), local("main.rs", EntryKind::File)];
+        let remote = remote(vec![
+            ("Cargo.toml", EntryKind::File),
+            ("main.rs", EntryKind::File),
+            ("old.rs", EntryKind::File),
+            ("notes.txt", EntryKind::File),
+            ("docs", EntryKind::Dir),
+            ("docs/readme.md", EntryKind::File),
+        ]);
+
+        // files the include doesn't select are never synced, so never extraneous, nor is the directory holding one
+        assert_eq!(deleted(&options, &local, &remote), ["old.rs"]);
+    }
+
+    #[test]
+    fn delete_keeps_excluded() {
+        let options = SyncOptions { delete: true, exclude: vec![Glob::new("target").unwrap()], ..Default::default() };
+        let remote = remote(vec![("target", EntryKind::Dir), ("target/debug", EntryKind::Dir), ("stale", EntryKind::File)]);
+        assert_eq!(deleted(&options, &[], &remote), ["stale"]);
+    }
+}
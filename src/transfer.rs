@@ -0,0 +1,383 @@
+use std::{io::{self, SeekFrom}, os::unix::fs::PermissionsExt, path::{Component, Path, PathBuf}, time::SystemTime};
+use futures::{StreamExt, TryStreamExt, stream};
+use openssh_sftp_client::{file::TokioCompatFile, metadata::MetaData};
+use tokio::{fs, io::{copy, AsyncSeekExt}};
+use crate::{client::{Client, GetOptions, PutOptions}, error::{Error, Result}, hash::{Hasher, HashingReader}, limit::LimitedReader, progress::ProgressStream, sftp::{apply_metadata, permissions_from_mode}};
+
+/// How many files are transferred at once over the shared sftp channel by default.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+#[derive(Debug)]
+pub(crate) enum EntryKind {
+    Dir,
+    File,
+    Symlink(PathBuf),
+}
+
+#[derive(Debug)]
+pub(crate) struct LocalEntry {
+    // relative to the root of the walk
+    pub(crate) path: PathBuf,
+    pub(crate) kind: EntryKind,
+    pub(crate) mode: u32,
+    pub(crate) len: u64,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RemoteEntry {
+    // relative to the root of the walk
+    pub(crate) path: PathBuf,
+    pub(crate) kind: EntryKind,
+    pub(crate) metadata: MetaData,
+}
+
+/// Outcome of a transfer that keeps going when single files fail.
+#[derive(Debug, Default)]
+pub struct TransferReport {
+    pub transferred: usize,
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl Client {
+    /// Creates `remote_dir` along with any missing parents, like `mkdir -p`.
+    pub async fn create_dir_all(&self, remote_dir: &Path) -> Result<()> {
+        let mut fs = self.sftp().await?.fs();
+
+        let mut missing = Vec::new();
+        let mut dir = remote_dir;
+        while fs.metadata(dir).await.is_err() {
+            missing.push(dir);
+            match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+                _ => break,
+            }
+        }
+
+        for dir in missing.into_iter().rev() {
+            fs.create_dir(dir).await.map_err(|e| Error::sftp_at(dir, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Uploads a local file and gives the remote copy the same mode.
+    pub async fn put_file(&self, local_path: &Path, remote_path: &Path, options: &PutOptions) -> Result<()> {
+        let mut local_file = fs::File::open(local_path).await?;
+        let local_metadata = local_file.metadata().await?;
+        let mode = local_metadata.permissions().mode();
+
+        let offset = if options.atomic {
+            0
+        } else {
+            let partial_len = match self.sftp().await?.fs().metadata(remote_path).await {
+                Ok(remote) => remote.len().unwrap_or(0),
+                Err(_) => 0,
+            };
+            self.resume_offset(options.resume, local_path, remote_path, partial_len, local_metadata.len()).await?
+        };
+
+        if offset > 0 {
+            let remote_file = self.sftp().await?.options().write(true).open(remote_path).await.map_err(|e| Error::sftp_at(remote_path, e))?;
+            let mut remote_file = Box::pin(TokioCompatFile::new(remote_file));
+            remote_file.seek(SeekFrom::Start(offset)).await?;
+
+            // hashing the kept prefix also moves the local file to the offset
+
+            let mut hasher = options.verify.map(Hasher::new);
+            match &mut hasher {
+                Some(hasher) => hasher.update_from(&mut local_file, offset).await?,
+                None => {
+                    local_file.seek(SeekFrom::Start(offset)).await?;
+                }
+            }
+            let local_file = LimitedReader::new(local_file, options.limit.clone());
+            let local_file = ProgressStream::new(local_file, options.progress.clone(), remote_path, offset, Some(local_metadata.len()));
+            let mut local_file = HashingReader::new(local_file, hasher);
+            copy(&mut local_file, &mut remote_file).await?;
+            drop(remote_file);
+
+            if let (Some(algorithm), Some(local)) = (options.verify, local_file.finish()) {
+                self.verify_remote_hash(remote_path, algorithm, local).await?;
+            }
+        } else {
+            self.put_stream(remote_path, local_file, Some(local_metadata.len()), options).await?;
+        }
+        self.sftp().await?.fs().set_permissions(remote_path, permissions_from_mode(mode)).await?;
+
+        Ok(())
+    }
+
+    /// Uploads a local directory tree, reproducing modes and symlinks.
+    ///
+    /// Up to `concurrency` files are in flight at once.
+    pub async fn put_dir(&self, local_dir: &Path, remote_dir: &Path, concurrency: usize, options: &PutOptions) -> Result<()> {
+        let entries = walk_local(local_dir).await?;
+
+        self.create_dir_all(remote_dir).await?;
+
+        // walk order puts parents before children, so plain creation is enough here
+
+        let mut fs = self.sftp().await?.fs();
+        for entry in &entries {
+            if let EntryKind::Dir = entry.kind {
+                let remote_path = remote_dir.join(&entry.path);
+                if fs.metadata(&remote_path).await.is_err() {
+                    fs.create_dir(&remote_path).await.map_err(|e| Error::sftp_at(&remote_path, e))?;
+                }
+            }
+        }
+
+        stream::iter(entries.iter().filter(|entry| matches!(entry.kind, EntryKind::File)))
+            .map(|entry| async move { self.put_file(&local_dir.join(&entry.path), &remote_dir.join(&entry.path), options).await })
+            .buffer_unordered(concurrency.max(1))
+            .try_collect::<()>()
+            .await?;
+
+        for entry in &entries {
+            if let EntryKind::Symlink(target) = &entry.kind {
+                let remote_path = remote_dir.join(&entry.path);
+
+                // replace a link left by an earlier upload, symlink refuses to overwrite
+                let _ = fs.remove_file(&remote_path).await;
+                fs.symlink(target, &remote_path).await?;
+            }
+        }
+
+        // modes last and deepest first, so a read-only directory doesn't block its own upload
+
+        for entry in entries.iter().rev() {
+            if let EntryKind::Dir = entry.kind {
+                fs.set_permissions(remote_dir.join(&entry.path), permissions_from_mode(entry.mode)).await?;
+            }
+        }
+        let mode = fs::metadata(local_dir).await?.permissions().mode();
+        fs.set_permissions(remote_dir, permissions_from_mode(mode)).await?;
+
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Downloads a remote directory tree, recreating directories and symlinks locally.
+    ///
+    /// Special files are skipped. An entry that can't be read or written locally is recorded in the
+    /// report and the rest of the tree is still downloaded.
+    pub async fn get_dir(&self, remote_dir: &Path, local_dir: &Path, concurrency: usize, options: &GetOptions) -> Result<TransferReport> {
+        let mut report = TransferReport::default();
+        let entries = self.walk_remote(remote_dir, &mut report).await?;
+
+        // only the target itself has to be creatable, everything below fails entry by entry
+
+        fs::create_dir_all(local_dir).await?;
+        for entry in &entries {
+            if let EntryKind::Dir = entry.kind {
+                let result = async {
+                    check_no_symlink(local_dir, &entry.path).await?;
+                    fs::create_dir_all(local_dir.join(&entry.path)).await?;
+                    Ok::<_, Error>(())
+                }.await;
+                if let Err(e) = result {
+                    report.failed.push((remote_dir.join(&entry.path), e));
+                }
+            }
+        }
+
+        let results = stream::iter(entries.iter().filter(|entry| matches!(entry.kind, EntryKind::File)))
+            .map(|entry| async move {
+                let result = async {
+                    check_no_symlink(local_dir, &entry.path).await?;
+                    self.get_file(&remote_dir.join(&entry.path), &local_dir.join(&entry.path), options).await
+                }.await;
+                (entry, result)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        for (entry, result) in results {
+            match result {
+                Ok(()) => report.transferred += 1,
+                Err(e) => report.failed.push((remote_dir.join(&entry.path), e)),
+            }
+        }
+
+        for entry in &entries {
+            if let EntryKind::Symlink(target) = &entry.kind {
+                let local_path = local_dir.join(&entry.path);
+                let result = async {
+                    check_no_symlink(local_dir, entry.path.parent().unwrap_or(Path::new(""))).await?;
+                    let _ = fs::remove_file(&local_path).await;
+                    fs::symlink(target, &local_path).await?;
+                    Ok::<_, Error>(())
+                }.await;
+                if let Err(e) = result {
+                    report.failed.push((remote_dir.join(&entry.path), e));
+                }
+            }
+        }
+
+        // directory modes last and deepest first, like put_dir
+
+        if options.preserve {
+            for entry in entries.iter().rev() {
+                if let EntryKind::Dir = entry.kind {
+                    if let Err(e) = apply_metadata(&local_dir.join(&entry.path), &entry.metadata) {
+                        report.failed.push((remote_dir.join(&entry.path), e));
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub(crate) async fn walk_remote(&self, root: &Path, report: &mut TransferReport) -> Result<Vec<RemoteEntry>> {
+        let sftp = self.sftp().await?;
+        let mut fs = sftp.fs();
+
+        // directories are only opened once it's their turn, a wide tree must not pin a handle per directory
+
+        let mut pending = vec![(PathBuf::new(), root.to_path_buf())];
+        let mut entries = Vec::new();
+
+        while let Some((dir, remote_dir)) = pending.pop() {
+            let children = match fs.open_dir(&remote_dir).await {
+                Ok(handle) => handle.read_dir().try_collect::<Vec<_>>().await,
+                Err(e) => Err(e),
+            };
+
+            // the root itself has to be readable, anything below is best effort
+            let children = match children {
+                Ok(children) => children,
+                Err(e) if dir.as_os_str().is_empty() => return Err(e.into()),
+                Err(e) => {
+                    report.failed.push((remote_dir, e.into()));
+                    continue;
+                }
+            };
+
+            for child in children {
+                let name = child.filename();
+                if name == Path::new(".") || name == Path::new("..") {
+                    continue;
+                }
+
+                // names come from the server, anything but one plain component could point outside the target
+                if !is_plain_name(name) {
+                    report.failed.push((remote_dir.clone(), Error::parse("file name", name.to_string_lossy())));
+                    continue;
+                }
+                let path = dir.join(name);
+                let Some(file_type) = child.file_type() else {
+                    continue;
+                };
+                let kind = if file_type.is_dir() {
+                    pending.push((path.clone(), root.join(&path)));
+                    EntryKind::Dir
+                } else if file_type.is_symlink() {
+                    match fs.read_link(root.join(&path)).await {
+                        Ok(target) => EntryKind::Symlink(target),
+                        Err(e) => {
+                            report.failed.push((root.join(&path), e.into()));
+                            continue;
+                        }
+                    }
+                } else if file_type.is_file() {
+                    EntryKind::File
+                } else {
+                    continue;
+                };
+                entries.push(RemoteEntry { path, kind, metadata: child.metadata() });
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+pub(crate) async fn walk_local(root: &Path) -> Result<Vec<LocalEntry>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(dir) = pending.pop() {
+        let mut read_dir = fs::read_dir(root.join(&dir)).await?;
+        let mut children = Vec::new();
+        while let Some(child) = read_dir.next_entry().await? {
+            children.push(child);
+        }
+        children.sort_by_key(|child| child.file_name());
+
+        for child in children {
+            let path = dir.join(child.file_name());
+            let metadata = fs::symlink_metadata(child.path()).await?;
+            let kind = if metadata.is_dir() {
+                pending.push(path.clone());
+                EntryKind::Dir
+            } else if metadata.is_symlink() {
+                EntryKind::Symlink(fs::read_link(child.path()).await?)
+            } else if metadata.is_file() {
+                EntryKind::File
+            } else {
+
+                // sockets, fifos and devices can't be reproduced over sftp
+
+                continue;
+            };
+            entries.push(LocalEntry { path, kind, mode: metadata.permissions().mode(), len: metadata.len(), modified: metadata.modified().ok() });
+        }
+    }
+
+    Ok(entries)
+}
+
+fn is_plain_name(name: &Path) -> bool {
+    let mut components = name.components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+// a symlink left below the target, by an earlier download or anyone else, must not redirect writes elsewhere
+
+async fn check_no_symlink(root: &Path, relative: &Path) -> Result<()> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path).await {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(Error::Io(io::Error::other(format!("refusing to write through local symlink {}", path.display()))));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_names_are_accepted() {
+        assert!(is_plain_name(Path::new("file.txt")));
+        assert!(is_plain_name(Path::new(".hidden")));
+        assert!(!is_plain_name(Path::new("..")));
+        assert!(!is_plain_name(Path::new("../../.bashrc")));
+        assert!(!is_plain_name(Path::new("/home/me/.ssh/authorized_keys")));
+        assert!(!is_plain_name(Path::new("sub/file")));
+        assert!(!is_plain_name(Path::new("")));
+    }
+
+    #[tokio::test]
+    async fn local_symlinks_are_not_followed() {
+        let root = std::env::temp_dir().join(format!("learning-openssh-symlink-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root).await;
+        fs::create_dir_all(root.join("real")).await.unwrap();
+        fs::symlink("/etc", root.join("sub")).await.unwrap();
+
+        assert!(check_no_symlink(&root, Path::new("real/new/file")).await.is_ok());
+        assert!(check_no_symlink(&root, Path::new("sub")).await.is_err());
+        assert!(check_no_symlink(&root, Path::new("sub/passwd")).await.is_err());
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}